@@ -0,0 +1,761 @@
+//! Whole-script parser
+//!
+//! `parse_script` turns the comment section of a txtar archive into a
+//! `Script`: one `Statement` per logical line, with continuation lines
+//! joined, heredoc bodies resolved and source line ranges recorded.
+//! The engine executes this AST; linters, formatters and editor tooling
+//! read the same structure.
+//!
+//! Line handling:
+//! - A line whose first character is `#` is a comment and never continues
+//! - A command line ending in `\` or `^` continues on the next line
+//! - `<<MARKER` arguments take the following lines verbatim, up to a line
+//!   that is exactly `MARKER` (surrounding whitespace ignored)
+//! - `if [cond]...` / `else` / `end` lines open, split and close a block;
+//!   blocks nest, and the statements inside belong to the block
+//! - `func name [param...]` ... `end` defines a macro; its body is a block
+//!   like an `if` branch, but `func` is only allowed at the top level
+//! - `include path` is a directive; the included script is filled in by
+//!   `load_script`, not by the parser
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::parser::{parse_conditions, parse_line, ArgFragment, ParseError, ScriptCondition, ScriptLine};
+
+/// A parsed script
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    /// Top-level statements in source order
+    pub statements: Vec<Statement>,
+}
+
+impl Script {
+    /// Every statement including those nested in blocks, in source order
+    /// (a block comes before the statements inside it)
+    pub fn all_statements(&self) -> Vec<&Statement> {
+        fn walk<'a>(statements: &'a [Statement], out: &mut Vec<&'a Statement>) {
+            for stmt in statements {
+                out.push(stmt);
+                match &stmt.kind {
+                    StatementKind::If(block) => {
+                        walk(&block.then_branch, out);
+                        walk(&block.else_branch, out);
+                    }
+                    StatementKind::Func(func) => walk(&func.body, out),
+                    StatementKind::Include(include) => walk(&include.statements, out),
+                    _ => {}
+                }
+            }
+        }
+        let mut out = Vec::new();
+        walk(&self.statements, &mut out);
+        out
+    }
+}
+
+/// One logical line of a script
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// Script lines this statement spans (0-based, end-exclusive),
+    /// including continuation lines and heredoc bodies
+    pub lines: Range<usize>,
+    /// What the statement is
+    pub kind: StatementKind,
+}
+
+impl Statement {
+    /// 1-based number of the statement's first line, as used in errors
+    pub fn line_number(&self) -> usize {
+        self.lines.start + 1
+    }
+}
+
+/// The kinds of statement
+#[derive(Debug, Clone)]
+pub enum StatementKind {
+    /// An empty or whitespace-only line
+    Blank,
+    /// A `#` comment line; `text` follows the `#`, trailing whitespace
+    /// removed. `section` is true when `#` is the first character: section
+    /// comments are logged when the script runs, indented ones are not.
+    Comment { text: String, section: bool },
+    /// A command
+    Command(CommandStatement),
+    /// An `if` block
+    If(IfBlock),
+    /// A `func` definition
+    Func(FuncDef),
+    /// An `include` directive
+    Include(Include),
+}
+
+/// A command statement
+#[derive(Debug, Clone)]
+pub struct CommandStatement {
+    /// The parsed command, continuation lines joined
+    pub line: ScriptLine,
+    /// Continuation character (`\` or `^`) if the command spans several lines
+    pub continuation: Option<char>,
+    /// Heredocs in argument order
+    pub heredocs: Vec<Heredoc>,
+}
+
+/// An `if [cond]...` block with an optional `else` branch
+#[derive(Debug, Clone)]
+pub struct IfBlock {
+    /// The `if` line as parsed; its arguments are the conditions
+    pub header: ScriptLine,
+    /// Conditions that must all hold for `then_branch` to run
+    pub conditions: Vec<ScriptCondition>,
+    /// Statements run when the conditions hold
+    pub then_branch: Vec<Statement>,
+    /// The `else` line, if any
+    pub else_line: Option<BlockLine>,
+    /// Statements run otherwise
+    pub else_branch: Vec<Statement>,
+    /// The `end` line; `None` only in partial parses of an unclosed block
+    pub end_line: Option<BlockLine>,
+}
+
+/// A `func name [param...]` definition.
+///
+/// Calling `name args...` runs `body` with `$1`, `$2`, ... set to the
+/// arguments and `$@` to all of them.
+#[derive(Debug, Clone)]
+pub struct FuncDef {
+    /// The `func` line as parsed
+    pub header: ScriptLine,
+    /// The macro's name, used as a command
+    pub name: String,
+    /// Parameter names, for usage messages. When given, calls must pass
+    /// exactly that many arguments, or at least that many less one if the
+    /// last ends in `...`.
+    pub params: Vec<String>,
+    /// Statements run on each call
+    pub body: Vec<Statement>,
+    /// The `end` line; `None` only in partial parses of an unclosed block
+    pub end_line: Option<BlockLine>,
+}
+
+impl FuncDef {
+    /// Whether `count` arguments fit the declared parameters
+    pub fn accepts(&self, count: usize) -> bool {
+        match self.params.last() {
+            None => true,
+            Some(last) if last.ends_with("...") => count + 1 >= self.params.len(),
+            Some(_) => count == self.params.len(),
+        }
+    }
+
+    /// `usage: name params...`, for calls with the wrong argument count
+    pub fn usage(&self) -> String {
+        let mut usage = format!("usage: {}", self.name);
+        for param in &self.params {
+            usage.push(' ');
+            usage.push_str(param);
+        }
+        usage
+    }
+}
+
+/// An `include path` directive
+#[derive(Debug, Clone)]
+pub struct Include {
+    /// The `include` line as parsed
+    pub line: ScriptLine,
+    /// The archive path as written, relative to the including file
+    pub path: String,
+    /// The resolved file, once loaded
+    pub file: Option<PathBuf>,
+    /// The included script's top-level statements, once loaded; their
+    /// line numbers refer to `file`
+    pub statements: Vec<Statement>,
+}
+
+/// An `else` or `end` line
+#[derive(Debug, Clone)]
+pub struct BlockLine {
+    /// Script line (0-based)
+    pub line: usize,
+    /// Trailing inline comment
+    pub comment: Option<String>,
+}
+
+/// A `<<MARKER` argument and its body
+#[derive(Debug, Clone)]
+pub struct Heredoc {
+    /// Index of the argument in `ScriptLine::raw_args`
+    pub arg: usize,
+    /// Index of the `<<MARKER` fragment within that argument
+    pub fragment: usize,
+    /// End marker
+    pub marker: String,
+    /// Body text: the body lines, continuations joined, separated by
+    /// newlines and ending with one unless empty. Empty lines before the
+    /// first other line are dropped, so a body of empty lines is empty.
+    pub body: String,
+    /// Body lines (0-based, end-exclusive), excluding the end marker
+    pub lines: Range<usize>,
+}
+
+/// Parse a whole script.
+///
+/// Parsing continues past errors, so every syntax error in the script
+/// is reported at once.
+pub fn parse_script(script: &str) -> Result<Script, Vec<ParseError>> {
+    let (script, errors) = parse_script_partial(script);
+    if errors.is_empty() {
+        Ok(script)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse a whole script, returning the statements that did parse along
+/// with the errors. Lines with syntax errors are left out; a command with
+/// an unterminated heredoc is kept, its body running to the end.
+pub fn parse_script_partial(script: &str) -> (Script, Vec<ParseError>) {
+    let lines: Vec<&str> = script.lines().collect();
+    let mut statements = Blocks::default();
+    let mut errors = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let first = i;
+        let line = lines[i];
+        i += 1;
+
+        if line.trim().is_empty() {
+            statements.push(Statement { lines: first..i, kind: StatementKind::Blank });
+            continue;
+        }
+        if let Some(text) = line.strip_prefix('#') {
+            statements.push(Statement {
+                lines: first..i,
+                kind: StatementKind::Comment { text: text.trim_end().to_string(), section: true },
+            });
+            continue;
+        }
+
+        let (merged, continuation) = join_continuation(line, &lines, &mut i);
+
+        let line_number = first + 1;
+        let mut parsed = match parse_line(&merged, line_number) {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => {
+                // Only an indented `# comment` is left
+                let text = merged.trim_start().strip_prefix('#').unwrap_or("").trim_end();
+                statements.push(Statement {
+                    lines: first..i,
+                    kind: StatementKind::Comment { text: text.to_string(), section: false },
+                });
+                continue;
+            }
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        if parsed.command == "include" {
+            match include_directive(parsed) {
+                Ok(include) => statements.push(Statement { lines: first..i, kind: StatementKind::Include(include) }),
+                Err(message) => errors.push(ParseError { message, line: line_number }),
+            }
+            continue;
+        }
+        if matches!(parsed.command.as_str(), "if" | "else" | "end" | "func") {
+            if let Err(e) = statements.keyword(&mut parsed, first, i) {
+                errors.push(e);
+            }
+            continue;
+        }
+
+        // exec's stdin may be a heredoc (`-stdin=<<EOF`); give the marker
+        // its own fragment so it's replaced like a plain `<<EOF`. Only
+        // exec's own options, before the program, are looked at.
+        if parsed.command == "exec" {
+            for frags in &mut parsed.raw_args {
+                let Some(first) = frags.first_mut().filter(|f| !f.quoted && f.s.starts_with('-')) else {
+                    break;
+                };
+                if first.s.starts_with("-stdin=<<") {
+                    let marker = first.s.split_off("-stdin=".len());
+                    frags.insert(1, ArgFragment { s: marker, quoted: false });
+                }
+            }
+        }
+
+        // Collect heredoc bodies, in argument order
+        let mut heredocs = Vec::new();
+        for (arg, frags) in parsed.raw_args.iter().enumerate() {
+            for (fragment, frag) in frags.iter().enumerate() {
+                let marker = match frag.s.strip_prefix("<<") {
+                    Some(marker) if !frag.quoted => marker,
+                    _ => continue,
+                };
+                if marker.is_empty() {
+                    errors.push(ParseError {
+                        message: "missing heredoc end marker after <<".into(),
+                        line: line_number,
+                    });
+                    continue;
+                }
+
+                let body_start = i;
+                let mut body = String::new();
+                let mut terminated = false;
+                while i < lines.len() {
+                    let body_line = lines[i];
+                    i += 1;
+                    // Body lines are joined like command lines
+                    let (body_line, _) = join_continuation(body_line, &lines, &mut i);
+                    if body_line.trim() == marker {
+                        terminated = true;
+                        break;
+                    }
+                    // Leading empty lines add nothing to an empty body
+                    if !body.is_empty() {
+                        body.push('\n');
+                    }
+                    body.push_str(&body_line);
+                }
+                if !body.is_empty() && !body.ends_with('\n') {
+                    body.push('\n');
+                }
+                if !terminated {
+                    errors.push(ParseError {
+                        message: format!("unterminated heredoc: missing end marker {}", marker),
+                        line: line_number,
+                    });
+                }
+                let body_end = if terminated { i - 1 } else { i };
+
+                heredocs.push(Heredoc {
+                    arg,
+                    fragment,
+                    marker: marker.to_string(),
+                    body,
+                    lines: body_start..body_end,
+                });
+            }
+        }
+
+        statements.push(Statement {
+            lines: first..i,
+            kind: StatementKind::Command(CommandStatement { line: parsed, continuation, heredocs }),
+        });
+    }
+
+    let statements = statements.finish(&mut errors, lines.len());
+    errors.sort_by_key(|e| e.line);
+    (Script { statements }, errors)
+}
+
+/// Validate an `include` line
+fn include_directive(line: ScriptLine) -> Result<Include, String> {
+    if line.negate || line.may_fail || !line.conditions.is_empty() || line.background {
+        return Err("include cannot take [cond], !, ? or & prefixes".into());
+    }
+    let [path] = line.raw_args.as_slice() else {
+        return Err("usage: include path".into());
+    };
+    let path: String = path.iter().map(|f| f.s.as_str()).collect();
+    if path.is_empty() {
+        return Err("usage: include path".into());
+    }
+    Ok(Include { line, path, file: None, statements: Vec::new() })
+}
+
+/// Statements collected so far, with the stack of open blocks
+#[derive(Default)]
+struct Blocks {
+    root: Vec<Statement>,
+    open: Vec<OpenBlock>,
+}
+
+/// An `if` or `func` block still waiting for its `end`
+struct OpenBlock {
+    first: usize,
+    block: Block,
+}
+
+enum Block {
+    If(IfBlock),
+    Func(FuncDef),
+}
+
+impl Block {
+    fn keyword(&self) -> &'static str {
+        match self {
+            Block::If(_) => "if",
+            Block::Func(_) => "func",
+        }
+    }
+
+    fn into_kind(self) -> StatementKind {
+        match self {
+            Block::If(block) => StatementKind::If(block),
+            Block::Func(func) => StatementKind::Func(func),
+        }
+    }
+}
+
+impl Blocks {
+    /// Add a statement to the innermost open branch
+    fn push(&mut self, stmt: Statement) {
+        match self.open.last_mut().map(|open| &mut open.block) {
+            Some(Block::If(block)) if block.else_line.is_some() => block.else_branch.push(stmt),
+            Some(Block::If(block)) => block.then_branch.push(stmt),
+            Some(Block::Func(func)) => func.body.push(stmt),
+            None => self.root.push(stmt),
+        }
+    }
+
+    /// Handle an `if`, `else`, `end` or `func` line spanning lines `first..end`
+    fn keyword(&mut self, line: &mut ScriptLine, first: usize, end: usize) -> Result<(), ParseError> {
+        let line_number = first + 1;
+        let error = |message: String| ParseError { message, line: line_number };
+        if line.negate || line.may_fail || !line.conditions.is_empty() || line.background {
+            return Err(error(format!("{} cannot take [cond], !, ? or & prefixes", line.command)));
+        }
+
+        if line.command == "if" {
+            if line.raw_args.is_empty() {
+                return Err(error("if requires at least one [condition]".into()));
+            }
+            let mut words = Vec::new();
+            for frags in &line.raw_args {
+                let word: String = frags.iter().map(|f| f.s.as_str()).collect();
+                if frags.iter().any(|f| f.quoted) {
+                    return Err(error(format!("expected [condition], found {:?}", word)));
+                }
+                words.push(word);
+            }
+            let conditions = parse_conditions(&words, line_number)?;
+            self.open.push(OpenBlock {
+                first,
+                block: Block::If(IfBlock {
+                    header: line.clone(),
+                    conditions,
+                    then_branch: Vec::new(),
+                    else_line: None,
+                    else_branch: Vec::new(),
+                    end_line: None,
+                }),
+            });
+            return Ok(());
+        }
+
+        if line.command == "func" {
+            let mut words = Vec::new();
+            for frags in &line.raw_args {
+                let word: String = frags.iter().map(|f| f.s.as_str()).collect();
+                if frags.iter().any(|f| f.quoted) || !is_func_word(&word) {
+                    return Err(error(format!("func: invalid name {:?}", word)));
+                }
+                words.push(word);
+            }
+            if words.is_empty() {
+                return Err(error("usage: func name [param...]".into()));
+            }
+            let name = words.remove(0);
+            if matches!(name.as_str(), "if" | "else" | "end" | "func" | "include") || name.ends_with("...") {
+                return Err(error(format!("func: invalid name {:?}", name)));
+            }
+            if let Some(param) = words.iter().rev().skip(1).find(|w| w.ends_with("...")) {
+                return Err(error(format!("func: only the last parameter can be variadic, not {}", param)));
+            }
+
+            // Still opened when misplaced, so that its `end` pairs up
+            let nested = !self.open.is_empty();
+            self.open.push(OpenBlock {
+                first,
+                block: Block::Func(FuncDef {
+                    header: line.clone(),
+                    name,
+                    params: words,
+                    body: Vec::new(),
+                    end_line: None,
+                }),
+            });
+            if nested {
+                return Err(error("func must be defined at the top level".into()));
+            }
+            return Ok(());
+        }
+
+        if !line.raw_args.is_empty() {
+            return Err(error(format!("{} takes no arguments", line.command)));
+        }
+        let block_line = BlockLine { line: first, comment: line.comment.take() };
+        if line.command == "else" {
+            return match self.open.last_mut().map(|open| &mut open.block) {
+                Some(Block::If(block)) if block.else_line.is_some() => Err(error("duplicate else".into())),
+                Some(Block::If(block)) => {
+                    block.else_line = Some(block_line);
+                    Ok(())
+                }
+                _ => Err(error("else without if".into())),
+            };
+        }
+
+        let Some(mut open) = self.open.pop() else {
+            return Err(error("end without if or func".into()));
+        };
+        match &mut open.block {
+            Block::If(block) => block.end_line = Some(block_line),
+            Block::Func(func) => func.end_line = Some(block_line),
+        }
+        self.push(Statement { lines: open.first..end, kind: open.block.into_kind() });
+        Ok(())
+    }
+
+    /// Close any blocks left open (reporting each) and return the statements.
+    /// An unclosed block runs to `end`, the end of the script.
+    fn finish(mut self, errors: &mut Vec<ParseError>, end: usize) -> Vec<Statement> {
+        while let Some(open) = self.open.pop() {
+            errors.push(ParseError {
+                message: format!("{} without end", open.block.keyword()),
+                line: open.first + 1,
+            });
+            self.push(Statement { lines: open.first..end, kind: open.block.into_kind() });
+        }
+        self.root
+    }
+}
+
+/// A macro or parameter name: letters, digits, `_` and `-`, not starting
+/// with a digit; a parameter may end in `...`
+fn is_func_word(word: &str) -> bool {
+    let name = word.strip_suffix("...").unwrap_or(word);
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Join `line` with the lines it continues onto, starting at `lines[*i]`
+/// and advancing `i` past them. Returns the joined line and the first
+/// continuation character, if any.
+fn join_continuation(line: &str, lines: &[&str], i: &mut usize) -> (String, Option<char>) {
+    let mut merged = line.to_string();
+    let mut continuation = None;
+    while let Some(c) = continuation_char(&merged) {
+        continuation.get_or_insert(c);
+        let trimmed = merged.trim_end();
+        merged = trimmed[..trimmed.len() - 1].trim_end().to_string();
+        if *i >= lines.len() {
+            break;
+        }
+        let next = lines[*i].trim();
+        *i += 1;
+        if !next.is_empty() {
+            merged.push(' ');
+            merged.push_str(next);
+        }
+    }
+    (merged, continuation)
+}
+
+/// The continuation character ending `line`, if any.
+/// It must be the last non-whitespace character.
+fn continuation_char(line: &str) -> Option<char> {
+    match line.trim_end().chars().last() {
+        Some(c @ ('\\' | '^')) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(stmt: &Statement) -> &CommandStatement {
+        match &stmt.kind {
+            StatementKind::Command(cmd) => cmd,
+            other => panic!("expected command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_script_statements() {
+        let script = parse_script("# section\n\nexec echo hi # note\n  # indented\n").unwrap();
+        assert_eq!(script.statements.len(), 4);
+        assert!(matches!(
+            &script.statements[0].kind,
+            StatementKind::Comment { text, section: true } if text == " section"
+        ));
+        assert!(matches!(script.statements[1].kind, StatementKind::Blank));
+        let cmd = command(&script.statements[2]);
+        assert_eq!(cmd.line.command, "exec");
+        assert_eq!(cmd.line.comment.as_deref(), Some("note"));
+        assert_eq!(script.statements[2].line_number(), 3);
+        assert!(matches!(
+            &script.statements[3].kind,
+            StatementKind::Comment { section: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_script_continuation() {
+        let script = parse_script("exec echo a \\\n  b ^\n  c\necho d\n").unwrap();
+        let stmt = &script.statements[0];
+        let cmd = command(stmt);
+        assert_eq!(stmt.lines, 0..3);
+        assert_eq!(cmd.continuation, Some('\\'));
+        assert_eq!(cmd.line.raw_args.len(), 4);
+        assert_eq!(script.statements[1].line_number(), 4);
+    }
+
+    #[test]
+    fn test_parse_script_heredoc() {
+        let script = parse_script("cmp stdout <<EOF\nline \\\n# not a comment\nEOF\necho x\n").unwrap();
+        let stmt = &script.statements[0];
+        let heredoc = &command(stmt).heredocs[0];
+        assert_eq!(heredoc.marker, "EOF");
+        assert_eq!((heredoc.arg, heredoc.fragment), (1, 0));
+        assert_eq!(heredoc.body, "line # not a comment\n");
+        assert_eq!(heredoc.lines, 1..3);
+        assert_eq!(stmt.lines, 0..4);
+        assert_eq!(script.statements.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_script_option_heredoc() {
+        let script = parse_script("exec -stdin=<<IN cat\nbody\nIN\n").unwrap();
+        let stmt = command(&script.statements[0]);
+        assert_eq!((stmt.heredocs[0].arg, stmt.heredocs[0].fragment), (0, 1));
+        assert_eq!(stmt.heredocs[0].body, "body\n");
+        assert_eq!(stmt.line.raw_args[0][0].s, "-stdin=");
+
+        // Other options, the program's own -stdin= and other commands'
+        // -stdin= stay literal
+        for line in [
+            "exec mytool --data=<<EOF\n",
+            "exec -x=<<Y mytool\n",
+            "exec mytool -stdin=<<IN\n",
+            "mycmd -stdin=<<IN\n",
+        ] {
+            let script = parse_script(line).unwrap();
+            let stmt = command(&script.statements[0]);
+            assert!(stmt.heredocs.is_empty(), "{}", line);
+            assert!(stmt.line.raw_args.iter().all(|frags| frags.len() == 1), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_script_empty_heredoc() {
+        let script = parse_script("cmp stdout <<EOF\nEOF\n").unwrap();
+        let heredoc = &command(&script.statements[0]).heredocs[0];
+        assert_eq!(heredoc.body, "");
+        assert_eq!(heredoc.lines, 1..1);
+
+        // Empty lines only: still an empty body
+        let script = parse_script("cmp stdout <<EOF\n\n\nEOF\n").unwrap();
+        let heredoc = &command(&script.statements[0]).heredocs[0];
+        assert_eq!(heredoc.body, "");
+        assert_eq!(heredoc.lines, 1..3);
+
+        // Leading empty lines are dropped, later ones and whitespace kept
+        let body = |script: &str| command(&parse_script(script).unwrap().statements[0]).heredocs[0].body.clone();
+        assert_eq!(body("cmp stdout <<EOF\n\na\n\nb\nEOF\n"), "a\n\nb\n");
+        assert_eq!(body("cmp stdout <<EOF\n  \nEOF\n"), "  \n");
+    }
+
+    #[test]
+    fn test_parse_script_heredoc_trailing_empty_lines() {
+        let body = |script: &str| command(&parse_script(script).unwrap().statements[0]).heredocs[0].body.clone();
+        assert_eq!(body("cmp stdout <<EOF\na\nEOF\n"), "a\n");
+        assert_eq!(body("cmp stdout <<EOF\na\n\nEOF\n"), "a\n");
+        assert_eq!(body("cmp stdout <<EOF\na\n\n\nEOF\n"), "a\n\n");
+    }
+
+    #[test]
+    fn test_parse_script_heredoc_continuation() {
+        let script = parse_script("cmp stdout <<EOF\na \\\n  b ^\n  c\nd\nEOF\necho x\n").unwrap();
+        let stmt = &script.statements[0];
+        let heredoc = &command(stmt).heredocs[0];
+        assert_eq!(heredoc.body, "a b c\nd\n");
+        assert_eq!(heredoc.lines, 1..5);
+        assert_eq!(script.statements[1].line_number(), 7);
+
+        // A continued line ending in the marker ends the body
+        let script = parse_script("cmp stdout <<EOF\na\n\\\nEOF\n").unwrap();
+        assert_eq!(command(&script.statements[0]).heredocs[0].body, "a\n");
+    }
+
+    #[test]
+    fn test_parse_script_if_blocks() {
+        let script = parse_script("if [unix] [!windows]\nexec a\nif [exec:git]\nexec b\nend\nelse # other\nexec c\nend\nexec d\n").unwrap();
+        assert_eq!(script.statements.len(), 2);
+        let StatementKind::If(block) = &script.statements[0].kind else { panic!("expected if") };
+        assert_eq!(script.statements[0].lines, 0..8);
+        assert_eq!(block.conditions.len(), 2);
+        assert!(block.conditions[1].negate);
+        assert_eq!(block.then_branch.len(), 2);
+        assert!(matches!(block.then_branch[1].kind, StatementKind::If(_)));
+        let else_line = block.else_line.as_ref().unwrap();
+        assert_eq!((else_line.line, else_line.comment.as_deref()), (5, Some("other")));
+        assert_eq!(block.else_branch.len(), 1);
+        assert_eq!(block.end_line.as_ref().unwrap().line, 7);
+        assert_eq!(script.all_statements().len(), 6);
+    }
+
+    #[test]
+    fn test_parse_script_if_block_errors() {
+        let errors = parse_script("else\nif\nif [a]\nelse\nelse\n! end\nend x\nif 'b'\n").unwrap_err();
+        let found: Vec<(usize, &str)> = errors.iter().map(|e| (e.line, e.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "else without if"),
+                (2, "if requires at least one [condition]"),
+                (3, "if without end"),
+                (5, "duplicate else"),
+                (6, "end cannot take [cond], !, ? or & prefixes"),
+                (7, "end takes no arguments"),
+                (8, "expected [condition], found \"b\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_script_func() {
+        let script = parse_script("func check name rest...\nif [unix]\nexec a $1\nend\nend # done\ncheck x\n").unwrap();
+        assert_eq!(script.statements.len(), 2);
+        let StatementKind::Func(func) = &script.statements[0].kind else { panic!("expected func") };
+        assert_eq!(func.name, "check");
+        assert_eq!(func.params, vec!["name", "rest..."]);
+        assert_eq!(func.body.len(), 1);
+        assert_eq!(func.end_line.as_ref().unwrap().comment.as_deref(), Some("done"));
+        assert!(func.accepts(1) && func.accepts(3) && !func.accepts(0));
+        assert_eq!(func.usage(), "usage: check name rest...");
+        assert_eq!(script.all_statements().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_script_func_errors() {
+        let errors = parse_script("func\nfunc 'a'\nfunc if\nfunc f a... b\nif [x]\nfunc g\nend\nend\nfunc h\nelse\n").unwrap_err();
+        let found: Vec<(usize, &str)> = errors.iter().map(|e| (e.line, e.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "usage: func name [param...]"),
+                (2, "func: invalid name \"a\""),
+                (3, "func: invalid name \"if\""),
+                (4, "func: only the last parameter can be variadic, not a..."),
+                (6, "func must be defined at the top level"),
+                (9, "func without end"),
+                (10, "else without if"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_script_reports_all_errors() {
+        let errors = parse_script("exec 'open\necho ok\n! ! exec x\ncmp stdout <<EOF\nbody\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+        assert!(errors[2].message.contains("unterminated heredoc"));
+    }
+}
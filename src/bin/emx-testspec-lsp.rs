@@ -0,0 +1,22 @@
+//! emx-testspec language server
+//!
+//! Serves LSP over stdin/stdout for .txtar testspec files.
+
+use std::io;
+use std::process::ExitCode;
+use emx_testspec::{Engine, LanguageServer};
+
+fn main() -> ExitCode {
+    let mut server = LanguageServer::new(Engine::new());
+    if let Err(e) = server.serve(io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    // LSP: exit code 0 only if `exit` followed `shutdown`
+    if server.is_shutdown() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
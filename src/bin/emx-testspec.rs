@@ -2,17 +2,30 @@
 //!
 //! Run testspec E2E tests from txtar files.
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 use std::process::ExitCode;
-use emx_testspec::{TestRunner, RunConfig};
+use emx_testspec::{TestRunner, RunConfig, TestResult, JsonReporter, junit_xml, parse_go_duration, format_archive};
+
+/// Output format for test results
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// PASS/FAIL lines and a summary
+    Human,
+    /// JSON-lines event stream (one event per line)
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "emx-testspec")]
 #[command(author = "nzinfo <li.monan@gmail.com>")]
 #[command(version)]
 #[command(about = "Run testspec E2E tests from txtar files")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Directory or file to test
     #[arg(default_value = ".")]
     path: PathBuf,
@@ -48,10 +61,67 @@ struct Cli {
     /// Show number of tests without running
     #[arg(long = "count")]
     count: bool,
+
+    /// Number of tests to run in parallel (0 = one per CPU)
+    #[arg(short = 'j', long = "jobs", default_value_t = 1)]
+    jobs: usize,
+
+    /// Rewrite golden files in the archives when cmp/cmpenv fails
+    /// (also enabled by TESTSCRIPT_UPDATE=1)
+    #[arg(long = "update")]
+    update: bool,
+
+    /// Write a JUnit XML report to this path
+    #[arg(long = "junit", value_name = "PATH")]
+    junit: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long = "format", value_enum, default_value_t = Format::Human)]
+    format: Format,
+
+    /// Per-script timeout, e.g. 30s or 2m (Go duration syntax)
+    #[arg(long = "timeout", value_parser = parse_go_duration)]
+    timeout: Option<std::time::Duration>,
+
+    /// Check scripts for errors without running them (same as `lint`)
+    #[arg(long = "check")]
+    check: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check scripts for errors without running them
+    Lint {
+        /// Directory or file to check
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Rewrite scripts in canonical form (file sections are left untouched)
+    Fmt {
+        /// Directory or file to format
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// List files that are not formatted instead of rewriting them
+        #[arg(long = "check")]
+        check: bool,
+    },
 }
 
 fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    let mut fmt_check = None;
+    match cli.command.take() {
+        Some(Command::Lint { path }) => {
+            cli.path = path;
+            cli.check = true;
+        }
+        Some(Command::Fmt { path, check }) => {
+            cli.path = path;
+            fmt_check = Some(check);
+        }
+        None => {}
+    }
 
     if cli.list_commands {
         print_commands();
@@ -82,9 +152,25 @@ fn main() -> ExitCode {
         verbose: cli.verbose,
         extensions: cli.extensions,
         setup: None,
+        jobs: cli.jobs,
+        update: cli.update || RunConfig::update_from_env(),
+        timeout: cli.timeout,
     };
 
-    let runner = TestRunner::new(config);
+    let mut runner = TestRunner::new(config);
+    if cli.format == Format::Json {
+        runner.set_reporter(Box::new(JsonReporter::new(std::io::stdout())));
+    }
+
+    if let Some(check_only) = fmt_check {
+        // An explicit file is formatted as is, not used as a filter
+        let file = is_file.then(|| cli.path.clone());
+        return fmt(&runner, file, check_only);
+    }
+
+    if cli.check {
+        return check(&runner);
+    }
 
     if cli.count {
         match runner.count_tests() {
@@ -107,13 +193,109 @@ fn main() -> ExitCode {
         }
     };
 
-    // Print results
+    if cli.format == Format::Human {
+        print_results(&result, cli.verbose);
+    }
+
+    if let Some(ref path) = cli.junit {
+        let xml = junit_xml(&result, &cli.path.to_string_lossy());
+        if let Err(e) = std::fs::write(path, xml) {
+            eprintln!("error: failed to write JUnit report {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if result.all_passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn check(runner: &TestRunner) -> ExitCode {
+    let results = match runner.check_all() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut count = 0;
+    for (file, diags) in &results {
+        for diag in diags {
+            println!("{}:{}: {}", file.display(), diag.line, diag.message);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("{} problem(s) in {} file(s)", count, results.len());
+        ExitCode::FAILURE
+    }
+}
+
+fn fmt(runner: &TestRunner, file: Option<PathBuf>, check_only: bool) -> ExitCode {
+    let files = match file {
+        Some(file) => vec![file],
+        None => match runner.discover() {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let mut failed = false;
+    for file in &files {
+        let data = match std::fs::read_to_string(file) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("error: {}: {}", file.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match format_archive(&data) {
+            Ok(f) => f,
+            Err(errors) => {
+                for e in errors {
+                    println!("{}:{}: {}", file.display(), e.line, e.message);
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == data {
+            continue;
+        }
+
+        if check_only {
+            println!("{}", file.display());
+            failed = true;
+        } else if let Err(e) = std::fs::write(file, formatted) {
+            eprintln!("error: failed to write {}: {}", file.display(), e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn print_results(result: &TestResult, verbose: bool) {
     for case in &result.cases {
         if case.skipped {
             println!("SKIP  {} - {}", case.name, case.error.as_deref().unwrap_or(""));
         } else if case.passed {
             println!("PASS  {} ({}ms)", case.name, case.duration.as_millis());
-            if cli.verbose && !case.log.is_empty() {
+            if verbose && !case.log.is_empty() {
                 for line in case.log.lines() {
                     println!("      {}", line);
                 }
@@ -139,12 +321,6 @@ fn main() -> ExitCode {
 
     println!();
     println!("{}", result.summary());
-
-    if result.all_passed() {
-        ExitCode::SUCCESS
-    } else {
-        ExitCode::FAILURE
-    }
 }
 
 fn print_commands() {
@@ -152,11 +328,7 @@ fn print_commands() {
     println!();
 
     let engine = emx_testspec::Engine::new();
-    let mut cmds: Vec<_> = engine.commands.iter().collect();
-    cmds.sort_by_key(|(name, _)| (*name).clone());
-
-    for (name, cmd) in &cmds {
-        let usage = cmd.usage();
+    for (name, usage) in engine.usages() {
         println!("  {:<12} {} {}", name, usage.summary, usage.args);
     }
 
@@ -1,8 +1,9 @@
-//! cmp / cmpenv — file comparison commands
+//! cmp / cmpenv / cmpjson — file comparison commands
 
 use crate::engine::{Cmd, CmdResult, CmdUsage};
 use crate::error::{ScriptError, ErrorKind};
 use crate::state::State;
+use serde_json::Value;
 use similar::TextDiff;
 
 // ──────────────────────────────────────────────────────────
@@ -11,6 +12,8 @@ use similar::TextDiff;
 
 pub(super) struct CmpCmd;
 
+const CMP_ARGS: &str = "[-q] [-lf] file1 file2";
+
 impl Cmd for CmpCmd {
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
         cmp_files(state, args, false)
@@ -19,9 +22,10 @@ impl Cmd for CmpCmd {
     fn usage(&self) -> CmdUsage {
         CmdUsage {
             summary: "Compare two files".into(),
-            args: "[-q] file1 file2".into(),
+            args: CMP_ARGS.into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -40,47 +44,269 @@ impl Cmd for CmpEnvCmd {
     fn usage(&self) -> CmdUsage {
         CmdUsage {
             summary: "Compare files with environment variable expansion".into(),
-            args: "[-q] file1 file2".into(),
+            args: CMP_ARGS.into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// cmpjson — compare JSON documents structurally
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct CmpJsonCmd;
+
+const CMPJSON_ARGS: &str = "[-ignore=path...] file1 file2";
+
+/// Most differences listed in a `cmpjson` failure
+const MAX_JSON_DIFFS: usize = 50;
+
+impl Cmd for CmpJsonCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let mut ignore = Vec::new();
+        let mut files: Vec<&str> = Vec::new();
+        for arg in args {
+            match arg.strip_prefix("-ignore=") {
+                Some(path) => ignore.push(parse_json_path(path).ok_or_else(|| {
+                    ScriptError::new(ErrorKind::UsageError, format!("cmpjson: invalid -ignore path: {}", path))
+                })?),
+                None => files.push(arg),
+            }
+        }
+        let [actual_name, want_name] = files[..] else {
+            return Err(ScriptError::usage("cmpjson", CMPJSON_ARGS));
+        };
+
+        let parse = |name: &str| -> Result<Value, ScriptError> {
+            let text = state.read_file(name).map_err(|e| {
+                ScriptError::new(ErrorKind::FileNotFound, format!("{}: {}", name, e))
+            })?;
+            serde_json::from_str(&text).map_err(|e| {
+                ScriptError::new(ErrorKind::ComparisonFailed, format!("cmpjson: {}: invalid JSON: {}", name, e))
+            })
+        };
+        let (actual, want) = (parse(actual_name)?, parse(want_name)?);
+
+        let mut diffs = Vec::new();
+        diff_json(&mut Vec::new(), Some(&actual), Some(&want), &ignore, &mut diffs);
+        if diffs.is_empty() {
+            return Ok(CmdResult::Ok);
+        }
+
+        let mut msg = format!("{} and {} differ:", actual_name, want_name);
+        for diff in diffs.iter().take(MAX_JSON_DIFFS) {
+            msg.push_str("\n  ");
+            msg.push_str(diff);
+        }
+        if diffs.len() > MAX_JSON_DIFFS {
+            msg.push_str(&format!("\n  ... and {} more", diffs.len() - MAX_JSON_DIFFS));
+        }
+        Err(ScriptError::new(ErrorKind::ComparisonFailed, msg))
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Compare two JSON files structurally, ignoring key order and whitespace".into(),
+            args: CMPJSON_ARGS.into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+/// One step of a JSON path; `None` in an `-ignore` path matches any step
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse a path like `$.items[*].id`, `$["a key"][0]` or `meta.time`
+/// (a leading `$.` is optional); `*` matches any key or index.
+fn parse_json_path(path: &str) -> Option<Vec<Option<Step>>> {
+    let owned;
+    let mut rest = match path.strip_prefix('$') {
+        Some(rest) => rest,
+        None => {
+            owned = format!(".{}", path);
+            &owned
+        }
+    };
+
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let step = match &after[..end] {
+                "" => return None,
+                "*" => None,
+                key => Some(Step::Key(key.to_string())),
+            };
+            steps.push(step);
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("[\"") {
+            // A quoted key, in JSON string syntax
+            let end = after.char_indices().find(|&(i, c)| c == '"' && !after[..i].ends_with('\\'))?.0;
+            let key: String = serde_json::from_str(&format!("\"{}\"", &after[..end])).ok()?;
+            steps.push(Some(Step::Key(key)));
+            rest = after[end + 1..].strip_prefix(']')?;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let step = match &after[..end] {
+                "*" => None,
+                index => Some(Step::Index(index.parse().ok()?)),
+            };
+            steps.push(step);
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+/// Render a path for messages: `$`, `$.items[0].id`, `$["a key"]`
+fn format_json_path(path: &[Step]) -> String {
+    let mut out = String::from("$");
+    for step in path {
+        match step {
+            Step::Key(key) => {
+                let plain = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if plain {
+                    out.push('.');
+                    out.push_str(key);
+                } else {
+                    out.push('[');
+                    out.push_str(&Value::from(key.as_str()).to_string());
+                    out.push(']');
+                }
+            }
+            Step::Index(i) => out.push_str(&format!("[{}]", i)),
+        }
+    }
+    out
+}
+
+/// A value for messages: compact JSON, shortened if long
+fn format_json_value(value: &Value) -> String {
+    let text = value.to_string();
+    match text.char_indices().nth(60) {
+        Some((cut, _)) => format!("{}...", &text[..cut]),
+        None => text,
+    }
+}
+
+/// Collect the differences between `actual` and `want` below `path`,
+/// skipping paths matched by `ignore`.
+fn diff_json(
+    path: &mut Vec<Step>,
+    actual: Option<&Value>,
+    want: Option<&Value>,
+    ignore: &[Vec<Option<Step>>],
+    out: &mut Vec<String>,
+) {
+    let ignored = ignore.iter().any(|pattern| {
+        pattern.len() == path.len()
+            && pattern.iter().zip(path.iter()).all(|(p, step)| p.as_ref().map_or(true, |p| p == step))
+    });
+    if ignored {
+        return;
+    }
+
+    let mut child = |path: &mut Vec<Step>, step: Step, actual: Option<&Value>, want: Option<&Value>| {
+        path.push(step);
+        diff_json(path, actual, want, ignore, out);
+        path.pop();
+    };
+    match (actual, want) {
+        (Some(Value::Object(a)), Some(Value::Object(w))) => {
+            let extra = a.keys().filter(|k| !w.contains_key(*k));
+            for key in w.keys().chain(extra) {
+                child(path, Step::Key(key.clone()), a.get(key), w.get(key));
+            }
         }
+        (Some(Value::Array(a)), Some(Value::Array(w))) => {
+            for i in 0..a.len().max(w.len()) {
+                child(path, Step::Index(i), a.get(i), w.get(i));
+            }
+        }
+        (Some(a), Some(w)) if json_equal(a, w) => {}
+        (Some(a), Some(w)) => out.push(format!(
+            "{}: got {}, want {}", format_json_path(path), format_json_value(a), format_json_value(w))),
+        (None, Some(w)) => out.push(format!("{}: missing, want {}", format_json_path(path), format_json_value(w))),
+        (Some(a), None) => out.push(format!("{}: got {}, want nothing", format_json_path(path), format_json_value(a))),
+        (None, None) => {}
+    }
+}
+
+/// Scalars are equal if they are the same value; numbers compare
+/// numerically, so `1` equals `1.0`
+fn json_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) if x.is_f64() || y.is_f64() => x.as_f64() == y.as_f64(),
+        _ => a == b,
     }
 }
 
 /// Shared cmp/cmpenv implementation
 fn cmp_files(state: &mut State, args: &[String], expand_env: bool) -> Result<CmdResult, ScriptError> {
     let mut quiet = false;
+    let mut lf = false;
     let mut files: Vec<&str> = Vec::new();
 
     for arg in args {
-        if arg == "-q" {
-            quiet = true;
-        } else {
-            files.push(arg);
+        match arg.as_str() {
+            "-q" => quiet = true,
+            "-lf" => lf = true,
+            _ => files.push(arg),
         }
     }
 
     if files.len() != 2 {
-        return Err(ScriptError::usage("cmp", "[-q] file1 file2"));
+        return Err(ScriptError::usage("cmp", CMP_ARGS));
     }
 
-    let mut content1 = state.read_file(files[0]).map_err(|e| {
+    let bytes1 = state.read_file_bytes(files[0]).map_err(|e| {
         ScriptError::new(ErrorKind::FileNotFound, format!("{}: {}", files[0], e))
     })?;
 
     // Read file2 - supports heredoc (<<...), stdout/stderr, and regular files.
     // Go-compatible: for regular files, file2 is read from disk.
-    let mut content2 = state.read_file(files[1]).map_err(|e| {
+    let bytes2 = state.read_file_bytes(files[1]).map_err(|e| {
         ScriptError::new(ErrorKind::FileNotFound, format!("{}: {}", files[1], e))
     })?;
 
+    // Binary content is compared byte for byte and shown as a hex diff
+    let (actual, mut content2) = match (String::from_utf8(bytes1), String::from_utf8(bytes2)) {
+        // Line endings count unless `-lf` asks to read CRLF as LF
+        (Ok(text1), Ok(text2)) if lf => (text1.replace("\r\n", "\n"), text2.replace("\r\n", "\n")),
+        (Ok(text1), Ok(text2)) => (text1, text2),
+        (data1, data2) => {
+            let data1 = data1.map_or_else(|e| e.into_bytes(), String::into_bytes);
+            let data2 = data2.map_or_else(|e| e.into_bytes(), String::into_bytes);
+            return cmp_binary(state, files[0], files[1], &data1, &data2, quiet);
+        }
+    };
+    let mut content1 = actual.clone();
+
     if expand_env {
         content1 = state.expand(&content1);
         content2 = state.expand(&content2);
     }
 
     if content1 != content2 {
-        let msg = format!("{} and {} differ", files[0], files[1]);
+        if state.update && update_golden(state, files[1], &actual, expand_env)? {
+            return Ok(CmdResult::Ok);
+        }
+
+        let mut msg = format!("{} and {} differ", files[0], files[1]);
+        if content1.replace("\r\n", "\n") == content2.replace("\r\n", "\n") {
+            msg.push_str(" in line endings only (use -lf to ignore them)");
+        }
         if !quiet {
             // Go-compatible: log unified diff output (Go uses internal/diff.Diff)
             let diff = TextDiff::from_lines(&content1, &content2);
@@ -94,3 +320,64 @@ fn cmp_files(state: &mut State, args: &[String], expand_env: bool) -> Result<Cmd
 
     Ok(CmdResult::Ok)
 }
+
+/// Compare non-UTF-8 content exactly, logging a diff of hex dumps.
+fn cmp_binary(
+    state: &mut State,
+    name1: &str,
+    name2: &str,
+    data1: &[u8],
+    data2: &[u8],
+    quiet: bool,
+) -> Result<CmdResult, ScriptError> {
+    if data1 == data2 {
+        return Ok(CmdResult::Ok);
+    }
+
+    let msg = format!("{} and {} differ (binary)", name1, name2);
+    if !quiet {
+        let (dump1, dump2) = (hex_dump(data1), hex_dump(data2));
+        let diff = TextDiff::from_lines(&dump1, &dump2);
+        let udiff = diff.unified_diff()
+            .header(name1, name2)
+            .to_string();
+        state.logf(&udiff);
+    }
+    Err(ScriptError::new(ErrorKind::ComparisonFailed, msg))
+}
+
+/// `hexdump -C` style dump: offset, 16 hex bytes, printable ASCII.
+fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii));
+    }
+    out
+}
+
+/// Update mode: rewrite the golden operand (an archive file or heredoc)
+/// with the actual content. Returns `false` if `name` cannot be updated.
+///
+/// For `cmpenv` the workdir path is folded back into `$WORK`, so the
+/// rewritten golden content still matches in a fresh workdir.
+fn update_golden(state: &mut State, name: &str, actual: &str, expand_env: bool) -> Result<bool, ScriptError> {
+    let content = if expand_env {
+        let work = state.workdir.to_string_lossy().to_string();
+        actual.replace(&work, "$WORK")
+    } else {
+        actual.to_string()
+    };
+
+    let updated = state.record_golden_update(name, &content).map_err(|e| {
+        ScriptError::new(ErrorKind::Io, format!("update {}: {}", name, e))
+    })?;
+    if updated {
+        state.logf(&format!("[updated {}]", name));
+    }
+    Ok(updated)
+}
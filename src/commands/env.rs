@@ -29,6 +29,7 @@ impl Cmd for CdCmd {
             args: "dir".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -42,10 +43,10 @@ pub(super) struct EchoCmd;
 impl Cmd for EchoCmd {
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
         // Go-compatible: echo returns WaitFunc → engine logs and sets stdout/stderr
-        state.stdout = args.join(" ") + "\n";
-        state.stderr = String::new();
+        state.stdout = (args.join(" ") + "\n").into_bytes();
+        state.stderr.clear();
         if !state.stdout.is_empty() {
-            state.logf(&format!("[stdout]\n{}", state.stdout));
+            state.logf(&format!("[stdout]\n{}", state.stdout_text()));
         }
         Ok(CmdResult::Ok)
     }
@@ -56,6 +57,7 @@ impl Cmd for EchoCmd {
             args: "[string...]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -75,11 +77,11 @@ impl Cmd for EnvCmd {
                 output.push_str(&format!("{}={}\n", k, v));
             }
             // Go-compatible: env returns WaitFunc → engine sets both stdout and stderr
-            state.stdout = output;
-            state.stderr = String::new();
+            state.stdout = output.into_bytes();
+            state.stderr.clear();
             // Go-compatible: engine logs stdout from WaitFunc results
             if !state.stdout.is_empty() {
-                state.logf(&format!("[stdout]\n{}", state.stdout));
+                state.logf(&format!("[stdout]\n{}", state.stdout_text()));
             }
             return Ok(CmdResult::Ok);
         }
@@ -99,10 +101,10 @@ impl Cmd for EnvCmd {
 
         if !output.is_empty() {
             // Go-compatible: env returns WaitFunc → engine sets both stdout and stderr
-            state.stdout = output;
-            state.stderr = String::new();
+            state.stdout = output.into_bytes();
+            state.stderr.clear();
             // Go-compatible: engine logs stdout from WaitFunc results
-            state.logf(&format!("[stdout]\n{}", state.stdout));
+            state.logf(&format!("[stdout]\n{}", state.stdout_text()));
         }
 
         Ok(CmdResult::Ok)
@@ -114,6 +116,7 @@ impl Cmd for EnvCmd {
             args: "[key[=value]...]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -1,59 +1,300 @@
-//! exec — execute a subprocess
+//! exec — execute a subprocess or pipeline; stdin — set its standard input;
+//! exitcode — assert its exit status
 
-use std::process::Command as ProcessCommand;
-use crate::engine::{Cmd, CmdResult, CmdUsage};
+use std::io::Write;
+use std::process::{Child, Command as ProcessCommand, Stdio};
+use std::time::Instant;
+use crate::engine::{Cmd, CmdContext, CmdResult, CmdUsage};
 use crate::error::{ScriptError, ErrorKind};
 use crate::state::State;
+use crate::signal;
+use super::flow::{parse_go_duration, set_job_name};
 
 pub(super) struct ExecCmd;
 
-impl Cmd for ExecCmd {
-    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
-        if args.is_empty() {
-            return Err(ScriptError::usage("exec", "program [args...]"));
-        }
+const EXEC_ARGS: &str = "[-timeout=duration] [-stdin=file] [-name=job] [-pty] program [args...] [| exec program [args...]]...";
 
-        // Go-compatible: convert forward slashes to OS path separator
-        // (Go does filepath.FromSlash(args[0]) before lookPath)
-        let program = args[0].replace('/', &std::path::MAIN_SEPARATOR.to_string());
-        let cmd_args = &args[1..];
+/// Options that may only come before the first stage of a pipeline
+const EXEC_OPTIONS: &[&str] = &["-timeout=", "-stdin=", "-name=", "-pty"];
 
-        // Use the script's PATH to look up the executable (Go-compatible)
-        let resolved = look_path(state, &program).map_err(|e| {
-            ScriptError::new(ErrorKind::CommandFailed,
-                format!("failed to execute '{}': {}", program, e))
-        })?;
+impl Cmd for ExecCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        self.run_with(state, args, &mut CmdContext::default())
+    }
 
-        let mut cmd = ProcessCommand::new(&resolved);
-        cmd.args(cmd_args);
-        cmd.current_dir(&state.pwd);
+    fn run_with(&self, state: &mut State, args: &[String], ctx: &mut CmdContext) -> Result<CmdResult, ScriptError> {
+        // Leading exec options; the first other argument is the program
+        let mut timeout = None;
+        let mut pty = false;
+        // Go-compatible: input set by `stdin` applies to the next exec only
+        let mut stdin = state.stdin.take();
+        let mut args = args;
+        let all_args = args.len();
+        while let Some(opt) = args.first() {
+            if let Some(value) = opt.strip_prefix("-timeout=") {
+                let d = parse_go_duration(value)
+                    .map_err(|e| ScriptError::new(ErrorKind::SyntaxError, format!("exec: {}", e)))?;
+                timeout = Some(d);
+            } else if let Some(name) = opt.strip_prefix("-name=") {
+                set_job_name(state, ctx, "exec", name)?;
+            } else if let Some(name) = opt.strip_prefix("-stdin=") {
+                let content = state.read_file_bytes(name).map_err(|e| {
+                    ScriptError::new(ErrorKind::FileNotFound,
+                        format!("exec: reading stdin {}: {}", name, e))
+                })?;
+                stdin = Some(content);
+            } else if opt == "-pty" {
+                pty = true;
+            } else {
+                break;
+            }
+            args = &args[1..];
+        }
 
-        // Pipe stdout/stderr so wait_with_output() captures them
-        cmd.stdout(std::process::Stdio::piped());
-        cmd.stderr(std::process::Stdio::piped());
+        // `| exec` starts the next stage of a pipeline
+        let options = all_args - args.len();
+        let pipes: Vec<usize> = ctx.pipes.iter().filter_map(|&p| p.checked_sub(options)).collect();
+        let stages = split_pipeline(args, &pipes);
+        for stage in &stages[1..] {
+            if stage.first().is_some_and(|a| EXEC_OPTIONS.iter().any(|o| a.starts_with(o))) {
+                return Err(ScriptError::new(ErrorKind::UsageError,
+                    "exec: options go before the first command of a pipeline"));
+            }
+        }
+        if stages.iter().any(|stage| stage.is_empty()) {
+            return Err(ScriptError::usage("exec", EXEC_ARGS));
+        }
+        let deadline = timeout.map(|d| Instant::now() + d);
 
-        // Set environment
-        cmd.env_clear();
-        for (k, v) in state.environ() {
-            cmd.env(k, v);
+        if pty {
+            if stages.len() > 1 {
+                return Err(ScriptError::new(ErrorKind::UsageError, "exec: -pty cannot be used with a pipeline"));
+            }
+            if stdin.is_some() {
+                return Err(ScriptError::new(ErrorKind::UsageError,
+                    "exec: a -pty program reads its input from send, not stdin"));
+            }
+            let (cmd, program) = command(state, args)?;
+            let (child, session) = crate::pty::spawn(cmd).map_err(|e| {
+                ScriptError::new(ErrorKind::CommandFailed,
+                    format!("failed to execute '{}' on a pty: {}", program, e))
+            })?;
+            let transcript = std::sync::Arc::clone(session.transcript());
+            state.pty = Some(session);
+            return Ok(CmdResult::Background(crate::engine::WaitHandle::Pty(child, transcript, deadline)));
         }
 
         // Always spawn — the engine decides whether to wait or push to background.
         // Go uses cmd.Start() and returns a WaitFunc closure.
-        let child = cmd.spawn().map_err(|e| {
-            ScriptError::new(ErrorKind::CommandFailed,
-                format!("failed to execute '{}': {}", program, e))
-        })?;
+        let mut children: Vec<Child> = Vec::with_capacity(stages.len());
+        for (i, stage) in stages.iter().enumerate() {
+            let input = match children.last_mut() {
+                Some(prev) => prev.stdout.take().map_or(Stdio::null(), Stdio::from),
+                // Go-compatible: stdin is empty unless set, never the runner's own
+                None if stdin.is_some() => Stdio::piped(),
+                None => Stdio::null(),
+            };
+            match spawn(state, stage, input, ctx.background) {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    // Don't leave the earlier stages running
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            }
+
+            // Feed stdin from a thread so a child that doesn't drain it (or a
+            // background job) can't block the script; EOF when the thread ends.
+            if i == 0 {
+                if let (Some(content), Some(mut pipe)) = (stdin.take(), children[0].stdin.take()) {
+                    std::thread::spawn(move || {
+                        let _ = pipe.write_all(&content);
+                    });
+                }
+            }
+        }
 
-        Ok(CmdResult::Background(crate::engine::WaitHandle::Process(child)))
+        let handle = if children.len() == 1 {
+            crate::engine::WaitHandle::Process(children.remove(0), deadline)
+        } else {
+            crate::engine::WaitHandle::Pipeline(children, deadline)
+        };
+        Ok(CmdResult::Background(handle))
     }
 
     fn usage(&self) -> CmdUsage {
         CmdUsage {
             summary: "Execute a command".into(),
-            args: "program [args...]".into(),
+            args: EXEC_ARGS.into(),
             regexp_args: None,
             async_: true,
+            creates_files: true,
+        }
+    }
+}
+
+/// Split exec arguments into pipeline stages at `pipes`, the indices of
+/// the `| exec` separators the parser found
+fn split_pipeline<'a>(args: &'a [String], pipes: &[usize]) -> Vec<&'a [String]> {
+    let mut stages = Vec::new();
+    let mut start = 0;
+    for &pipe in pipes {
+        if pipe < start || pipe + 1 >= args.len() {
+            continue;
+        }
+        stages.push(&args[start..pipe]);
+        start = pipe + 2;
+    }
+    stages.push(&args[start..]);
+    stages
+}
+
+/// Start one program with the script's directory and environment, stdout
+/// and stderr piped, and `stdin` as its standard input
+fn spawn(state: &State, args: &[String], stdin: Stdio, background: bool) -> Result<Child, ScriptError> {
+    let (mut cmd, program) = command(state, args)?;
+
+    // A background job leads a new process group, so `kill` and the
+    // cleanup at script end reach whatever the program starts too
+    #[cfg(unix)]
+    if background {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    }
+
+    // Pipe stdout/stderr so the engine's wait captures them
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.stdin(stdin);
+
+    cmd.spawn().map_err(|e| {
+        ScriptError::new(ErrorKind::CommandFailed,
+            format!("failed to execute '{}': {}", program, e))
+    })
+}
+
+/// Build the command for one program with the script's directory and
+/// environment; also returns the program name for error messages
+fn command(state: &State, args: &[String]) -> Result<(ProcessCommand, String), ScriptError> {
+    // Go-compatible: convert forward slashes to OS path separator
+    // (Go does filepath.FromSlash(args[0]) before lookPath)
+    let program = args[0].replace('/', &std::path::MAIN_SEPARATOR.to_string());
+    let cmd_args = &args[1..];
+
+    // Use the script's PATH to look up the executable (Go-compatible)
+    let resolved = look_path(state, &program).map_err(|e| {
+        ScriptError::new(ErrorKind::CommandFailed,
+            format!("failed to execute '{}': {}", program, e))
+    })?;
+
+    let mut cmd = ProcessCommand::new(&resolved);
+    cmd.args(cmd_args);
+    cmd.current_dir(&state.pwd);
+
+    // Set environment
+    cmd.env_clear();
+    for (k, v) in state.environ() {
+        cmd.env(k, v);
+    }
+    Ok((cmd, program))
+}
+
+// ──────────────────────────────────────────────────────────
+// exitcode
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct ExitCodeCmd;
+
+impl Cmd for ExitCodeCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        match args {
+            [] => Err(ScriptError::usage("exitcode", "code|SIGNAL...")),
+            [want] => check_status(want, state.exit_code, state.exit_signal).map(|_| CmdResult::Ok),
+            // One status per pipeline stage
+            wants => {
+                if wants.len() != state.pipe_status.len() {
+                    return Err(ScriptError::new(ErrorKind::ExitCodeMismatch, format!(
+                        "last exec had {} stage(s), want {}", state.pipe_status.len(), wants.len())));
+                }
+                for (i, (want, stage)) in wants.iter().zip(&state.pipe_status).enumerate() {
+                    check_status(want, stage.exit_code, stage.signal).map_err(|mut e| {
+                        if e.kind == ErrorKind::ExitCodeMismatch {
+                            e.message = format!("stage {}: {}", i + 1, e.message);
+                        }
+                        e
+                    })?;
+                }
+                Ok(CmdResult::Ok)
+            }
+        }
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Check the exit code (or signal) of the last exec, or of each pipeline stage".into(),
+            args: "code|SIGNAL...".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+/// Check an exit status against `want`, an exit code or signal name
+fn check_status(want: &str, code: Option<i32>, sig: Option<i32>) -> Result<(), ScriptError> {
+    let got = match (code, sig) {
+        (Some(code), _) => format!("exit code {}", code),
+        (None, Some(sig)) => format!("killed by {}", signal::name(sig)),
+        (None, None) => "no exit status recorded".to_string(),
+    };
+
+    let matched = if let Ok(want) = want.parse::<i32>() {
+        code == Some(want)
+    } else if let Some(want) = signal::parse(want) {
+        sig == Some(want)
+    } else {
+        return Err(ScriptError::new(ErrorKind::UsageError,
+            format!("exitcode: invalid exit code or signal: {}", want)));
+    };
+
+    if matched {
+        Ok(())
+    } else {
+        Err(ScriptError::new(ErrorKind::ExitCodeMismatch, format!("{}, want {}", got, want)))
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// stdin
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct StdinCmd;
+
+impl Cmd for StdinCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        if args.len() != 1 {
+            return Err(ScriptError::usage("stdin", "file"));
+        }
+
+        // Go-compatible: the content is read now, so `stdin stdout` pipes the
+        // previous command's output into the next exec
+        let content = state.read_file_bytes(&args[0]).map_err(|e| {
+            ScriptError::new(ErrorKind::FileNotFound,
+                format!("stdin: reading {}: {}", args[0], e))
+        })?;
+        state.stdin = Some(content);
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Set standard input for the next exec".into(),
+            args: "file".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
         }
     }
 }
@@ -77,6 +77,7 @@ impl Cmd for ExistsCmd {
             args: "[-readonly] [-exec] file...".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -93,22 +94,22 @@ impl Cmd for CatCmd {
             return Err(ScriptError::usage("cat", "file..."));
         }
 
-        let mut output = String::new();
+        let mut output = Vec::new();
         for file in args {
             // Go-compatible: cat always reads from disk (never virtual stdout/stderr)
             let path = state.resolve_path(file);
-            let content = std::fs::read_to_string(&path).map_err(|e| {
+            let content = std::fs::read(&path).map_err(|e| {
                 ScriptError::new(ErrorKind::FileNotFound, format!("{}: {}", file, e))
             })?;
-            output.push_str(&content);
+            output.extend_from_slice(&content);
         }
 
         // Go-compatible: cat returns WaitFunc → engine logs and sets stdout/stderr
         state.stdout = output;
-        state.stderr = String::new();
+        state.stderr.clear();
         // Go-compatible: engine logs stdout from WaitFunc results
         if !state.stdout.is_empty() {
-            state.logf(&format!("[stdout]\n{}", state.stdout));
+            state.logf(&format!("[stdout]\n{}", state.stdout_text()));
         }
         Ok(CmdResult::Ok)
     }
@@ -119,6 +120,7 @@ impl Cmd for CatCmd {
             args: "file...".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -150,8 +152,8 @@ impl Cmd for CpCmd {
         for src in srcs {
             // Read source: support "stdout" and "stderr" virtual files
             let (data, mode) = match src.as_str() {
-                "stdout" => (state.stdout.as_bytes().to_vec(), 0o666u32),
-                "stderr" => (state.stderr.as_bytes().to_vec(), 0o666u32),
+                "stdout" => (state.stdout.clone(), 0o666u32),
+                "stderr" => (state.stderr.clone(), 0o666u32),
                 _ => {
                     let src_path = state.resolve_path(src);
                     let data = std::fs::read(&src_path).map_err(|e| {
@@ -206,6 +208,7 @@ impl Cmd for CpCmd {
             args: "src... dst".into(),
             regexp_args: None,
             async_: false,
+            creates_files: true,
         }
     }
 }
@@ -238,6 +241,7 @@ impl Cmd for MkdirCmd {
             args: "dir...".into(),
             regexp_args: None,
             async_: false,
+            creates_files: true,
         }
     }
 }
@@ -276,6 +280,7 @@ impl Cmd for RmCmd {
             args: "path...".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -312,6 +317,7 @@ impl Cmd for MvCmd {
             args: "old new".into(),
             regexp_args: None,
             async_: false,
+            creates_files: true,
         }
     }
 }
@@ -375,6 +381,7 @@ impl Cmd for ChmodCmd {
             args: "perm paths...".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -431,6 +438,7 @@ impl Cmd for SymlinkCmd {
             args: "path -> target".into(),
             regexp_args: None,
             async_: false,
+            creates_files: true,
         }
     }
 }
@@ -1,8 +1,11 @@
-//! Flow control commands: stop, skip, sleep, wait
+//! Flow control commands: stop, skip, sleep, wait, kill, eventually
 
-use crate::engine::{Cmd, CmdResult, CmdUsage};
+use std::time::{Duration, Instant};
+
+use crate::engine::{Cmd, CmdContext, CmdResult, CmdUsage, WaitHandle, WrapCmd};
 use crate::error::{ErrorKind, ScriptError};
-use crate::state::State;
+use crate::signal;
+use crate::state::{BackgroundCmd, State};
 
 // ──────────────────────────────────────────────────────────
 // stop — stop script execution
@@ -30,6 +33,7 @@ impl Cmd for StopCmd {
             args: "[message]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -56,6 +60,7 @@ impl Cmd for SkipCmd {
             args: "[reason]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -93,6 +98,7 @@ impl Cmd for SleepCmd {
             args: "duration".into(),
             regexp_args: None,
             async_: true,
+            creates_files: false,
         }
     }
 }
@@ -105,15 +111,13 @@ pub(super) struct WaitCmd;
 
 impl Cmd for WaitCmd {
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
-        if !args.is_empty() {
-            return Err(ScriptError::usage("wait", ""));
-        }
-
-        let bg_cmds: Vec<crate::state::BackgroundCmd> = std::mem::take(&mut state.background);
+        // With job names, harvest only those; otherwise all background commands
+        let bg_cmds = take_jobs(state, "wait", args)?;
 
         let mut stdouts = Vec::new();
         let mut stderrs = Vec::new();
         let mut errors = Vec::new();
+        let mut timed_out = false;
 
         for bg in bg_cmds {
             let before_args = if bg.args.is_empty() { "" } else { " " };
@@ -128,37 +132,47 @@ impl Cmd for WaitCmd {
                     .join(" ")
             ));
 
-            let (stdout, stderr, err) = bg.handle.wait();
+            let label = bg.label().to_string();
+            let output = bg.handle.wait(state.deadline);
+            // The last harvested job's status wins
+            state.set_exit_status(output.exit_code, output.signal);
+            state.pipe_status = output.stages;
+            let killed_by_kill = bg.signaled.is_some() && output.signal == bg.signaled;
 
-            if !stdout.is_empty() {
-                state.logf(&format!("[stdout]\n{}", stdout));
-                stdouts.push(stdout);
+            if !output.stdout.is_empty() {
+                state.logf(&format!("[stdout]\n{}", String::from_utf8_lossy(&output.stdout)));
+                stdouts.push(output.stdout);
             }
-            if !stderr.is_empty() {
-                state.logf(&format!("[stderr]\n{}", stderr));
-                stderrs.push(stderr);
+            if !output.stderr.is_empty() {
+                state.logf(&format!("[stderr]\n{}", String::from_utf8_lossy(&output.stderr)));
+                stderrs.push(output.stderr);
             }
 
-            if let Some(err_detail) = err {
-                let err_msg = format!("{}: {}", bg.name, err_detail);
+            if let Some(err_detail) = output.error {
+                let err_msg = format!("{}: {}", label, err_detail);
                 state.logf(&format!("[{}]", err_msg));
-                if bg.negate {
+                if output.timed_out {
+                    // A timeout is fatal regardless of `!` / `?`
+                    timed_out = true;
+                    errors.push(err_msg);
+                } else if bg.negate || killed_by_kill {
                     // Expected failure — ok
                 } else if !bg.may_fail {
                     errors.push(err_msg);
                 }
             } else if bg.negate {
-                let err_msg = format!("{}: unexpected success", bg.name);
+                let err_msg = format!("{}: unexpected success", label);
                 errors.push(err_msg);
             }
         }
 
         // Go-compatible: stdout/stderr are the concatenation of all background outputs
-        state.stdout = stdouts.join("");
-        state.stderr = stderrs.join("");
+        state.stdout = stdouts.concat();
+        state.stderr = stderrs.concat();
 
         if !errors.is_empty() {
-            return Err(ScriptError::new(ErrorKind::WaitError, errors.join("\n")));
+            let kind = if timed_out { ErrorKind::Timeout } else { ErrorKind::WaitError };
+            return Err(ScriptError::new(kind, errors.join("\n")));
         }
 
         Ok(CmdResult::Ok)
@@ -167,9 +181,151 @@ impl Cmd for WaitCmd {
     fn usage(&self) -> CmdUsage {
         CmdUsage {
             summary: "Wait for background commands to complete".into(),
-            args: "".into(),
+            args: "[name...]".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// kill — signal background commands
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct KillCmd;
+
+impl Cmd for KillCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let (sig, names) = match args.first() {
+            Some(flag) if flag.starts_with('-') => {
+                let sig = signal::parse(&flag[1..]).ok_or_else(|| {
+                    ScriptError::new(ErrorKind::UsageError,
+                        format!("kill: unknown signal: {}", flag))
+                })?;
+                (sig, &args[1..])
+            }
+            _ => (signal::SIGKILL, args),
+        };
+
+        check_job_names(state, "kill", names)?;
+
+        // The jobs stay queued: `wait` harvests them and checks the outcome.
+        // One failure doesn't stop the others from being signalled.
+        let mut threads = Vec::new();
+        let mut errors = Vec::new();
+        for bg in state.background.iter_mut() {
+            if !names.is_empty() && !bg.job.as_ref().is_some_and(|j| names.contains(j)) {
+                continue;
+            }
+            // `sleep &` has nothing to signal; it finishes on its own
+            if matches!(bg.handle, WaitHandle::Thread(_)) {
+                threads.push(bg.label().to_string());
+                continue;
+            }
+            match bg.handle.signal(sig) {
+                Ok(()) => bg.signaled = Some(sig),
+                Err(e) => errors.push(format!("kill: {}: {}", bg.label(), e)),
+            }
+        }
+        for label in threads {
+            state.logf(&format!("[kill: {} is not a process, left to finish]", label));
+        }
+
+        if !errors.is_empty() {
+            return Err(ScriptError::new(ErrorKind::CommandFailed, errors.join("\n")));
+        }
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Send a signal to background commands (default SIGKILL)".into(),
+            args: "[-SIGNAL] [name...]".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// eventually — retry a command until it succeeds
+// ──────────────────────────────────────────────────────────
+
+/// How long `eventually` keeps retrying unless given `-timeout=`
+const EVENTUALLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between `eventually` attempts unless given `-interval=`
+const EVENTUALLY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Retries the wrapped command until it succeeds or the timeout passes,
+/// then fails with its last error. Only the last attempt's output stays
+/// in the log.
+pub(super) struct EventuallyCmd;
+
+impl WrapCmd for EventuallyCmd {
+    fn options(&self, raw_args: &[String]) -> usize {
+        raw_args
+            .iter()
+            .take_while(|arg| arg.starts_with("-timeout=") || arg.starts_with("-interval="))
+            .count()
+    }
+
+    fn run(
+        &self,
+        state: &mut State,
+        options: &[String],
+        inner: &mut dyn FnMut(&mut State) -> Result<(), ScriptError>,
+    ) -> Result<(), ScriptError> {
+        let mut timeout = EVENTUALLY_TIMEOUT;
+        let mut interval = EVENTUALLY_INTERVAL;
+        for opt in options {
+            let (value, target) = match opt.strip_prefix("-timeout=") {
+                Some(value) => (value, &mut timeout),
+                None => (opt.strip_prefix("-interval=").unwrap_or_default(), &mut interval),
+            };
+            *target = parse_go_duration(value).map_err(|e| ScriptError::syntax(format!("eventually: {}", e)))?;
+        }
+
+        let mut deadline = Instant::now() + timeout;
+        if let Some(script_deadline) = state.deadline {
+            deadline = deadline.min(script_deadline);
+        }
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let mark = state.log.len();
+            let mut err = match inner(state) {
+                Ok(()) => {
+                    if attempts > 1 {
+                        state.logf(&format!("[passed on attempt {}]", attempts));
+                    }
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            // Retrying can't fix these
+            if err.is_skip() || err.is_stop() || matches!(err.kind, ErrorKind::SyntaxError | ErrorKind::UsageError) {
+                return Err(err);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                err.message = format!("eventually: gave up after {} attempts: {}", attempts, err.message);
+                return Err(err);
+            }
+            state.log.truncate(mark);
+            std::thread::sleep(interval.min(deadline - now));
+        }
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Retry a command until it succeeds (default: every 100ms for up to 10s)".into(),
+            args: "[-timeout=duration] [-interval=duration] command [args...]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -178,8 +334,51 @@ impl Cmd for WaitCmd {
 // Helpers
 // ──────────────────────────────────────────────────────────
 
+/// Check that every name refers to a queued background job.
+fn check_job_names(state: &State, cmd: &str, names: &[String]) -> Result<(), ScriptError> {
+    for name in names {
+        if !state.background.iter().any(|bg| bg.job.as_ref() == Some(name)) {
+            return Err(ScriptError::new(ErrorKind::UsageError,
+                format!("{}: no background job named {}", cmd, name)));
+        }
+    }
+    Ok(())
+}
+
+/// Name the background job the command will start (`-name=`) in `ctx`;
+/// the engine attaches it to the background entry.
+pub(super) fn set_job_name(state: &State, ctx: &mut CmdContext, cmd: &str, name: &str) -> Result<(), ScriptError> {
+    if name.is_empty() {
+        return Err(ScriptError::new(ErrorKind::UsageError, format!("{}: empty job name", cmd)));
+    }
+    if !ctx.background {
+        return Err(ScriptError::new(ErrorKind::UsageError,
+            format!("{}: -name= is for background commands (&)", cmd)));
+    }
+    if state.background.iter().any(|bg| bg.job.as_deref() == Some(name)) {
+        return Err(ScriptError::new(ErrorKind::UsageError,
+            format!("{}: background job {} is already running", cmd, name)));
+    }
+    ctx.job = Some(name.to_string());
+    Ok(())
+}
+
+/// Remove the named jobs (all jobs if `names` is empty) from the background
+/// queue, keeping their start order.
+fn take_jobs(state: &mut State, cmd: &str, names: &[String]) -> Result<Vec<BackgroundCmd>, ScriptError> {
+    check_job_names(state, cmd, names)?;
+    if names.is_empty() {
+        return Ok(std::mem::take(&mut state.background));
+    }
+    let (taken, rest) = std::mem::take(&mut state.background)
+        .into_iter()
+        .partition(|bg| bg.job.as_ref().is_some_and(|j| names.contains(j)));
+    state.background = rest;
+    Ok(taken)
+}
+
 /// Parse a Go-style duration string (e.g., "1s", "100ms", "1m30s", "500us").
-fn parse_go_duration(s: &str) -> Result<std::time::Duration, String> {
+pub fn parse_go_duration(s: &str) -> Result<std::time::Duration, String> {
     let mut total_nanos: u128 = 0;
     let mut chars = s.chars().peekable();
     let mut found_unit = false;
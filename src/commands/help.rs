@@ -52,11 +52,11 @@ impl Cmd for HelpCmd {
             }
         }
 
-        state.stdout = output;
-        state.stderr = String::new();
+        state.stdout = output.into_bytes();
+        state.stderr.clear();
         // Go-compatible: help returns WaitFunc → engine logs stdout
         if !state.stdout.is_empty() {
-            state.logf(&format!("[stdout]\n{}", state.stdout));
+            state.logf(&format!("[stdout]\n{}", state.stdout_text()));
         }
         Ok(CmdResult::Ok)
     }
@@ -67,6 +67,7 @@ impl Cmd for HelpCmd {
             args: "[command...]".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
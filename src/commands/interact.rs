@@ -0,0 +1,118 @@
+//! expect / send — drive a program started with `exec -pty`
+
+use std::time::{Duration, Instant};
+use crate::engine::{Cmd, CmdResult, CmdUsage, first_non_flag};
+use crate::error::{ScriptError, ErrorKind};
+use crate::pty::{PtySession, Unmatched};
+use crate::state::State;
+use super::flow::parse_go_duration;
+
+/// How long `expect` waits unless given `-timeout=`
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The session of the last `exec -pty`
+fn session<'a>(state: &'a mut State, cmd: &str) -> Result<&'a mut PtySession, ScriptError> {
+    state.pty.as_mut().ok_or_else(|| {
+        ScriptError::new(ErrorKind::UsageError, format!("{}: no pty session (start one with exec -pty)", cmd))
+    })
+}
+
+// ──────────────────────────────────────────────────────────
+// expect — wait for terminal output matching a pattern
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct ExpectCmd;
+
+impl Cmd for ExpectCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let mut timeout = EXPECT_TIMEOUT;
+        let mut args = args;
+        if let Some(value) = args.first().and_then(|a| a.strip_prefix("-timeout=")) {
+            timeout = parse_go_duration(value)
+                .map_err(|e| ScriptError::new(ErrorKind::SyntaxError, format!("expect: {}", e)))?;
+            args = &args[1..];
+        }
+        let [pattern] = args else {
+            return Err(ScriptError::usage("expect", "[-timeout=duration] pattern"));
+        };
+
+        // Like `stdout`, but terminal lines end in \r\n
+        let re = regex::bytes::RegexBuilder::new(pattern)
+            .multi_line(true)
+            .crlf(true)
+            .size_limit(1 << 20)
+            .build()
+            .map_err(|e| ScriptError::new(ErrorKind::SyntaxError, format!("expect: invalid regex: {}", e)))?;
+
+        let mut deadline = Instant::now() + timeout;
+        if let Some(script_deadline) = state.deadline {
+            deadline = deadline.min(script_deadline);
+        }
+        let session = session(state, "expect")?;
+        let from = session.matched;
+        let transcript = std::sync::Arc::clone(session.transcript());
+
+        // Each match consumes the output up to its end, so a later expect
+        // only sees what came after
+        let result = transcript.wait_for(from, deadline, |data| re.find(data).map(|m| m.end()));
+        let contents = transcript.contents();
+        match result {
+            Ok(end) => {
+                session.matched = from + end;
+                state.stdout = crate::pty::to_output(&contents);
+                Ok(CmdResult::Ok)
+            }
+            Err(why) => {
+                let (kind, reason) = match why {
+                    Unmatched::TimedOut => (ErrorKind::Timeout, format!("timed out after {:?}", timeout)),
+                    Unmatched::Closed => (ErrorKind::PatternMismatch, "terminal closed".to_string()),
+                };
+                let pending = crate::pty::to_output(contents.get(from..).unwrap_or_default());
+                let pending = String::from_utf8_lossy(&pending).into_owned();
+                state.stdout = crate::pty::to_output(&contents);
+                Err(ScriptError::new(kind, format!(
+                    "expect: {} waiting for /{}/\nunmatched output:\n{}", reason, pattern, pending)))
+            }
+        }
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Wait for pty output matching pattern".into(),
+            args: "[-timeout=duration] pattern".into(),
+            regexp_args: Some(first_non_flag),
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// send — type text into the terminal
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct SendCmd;
+
+impl Cmd for SendCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let [text] = args else {
+            return Err(ScriptError::usage("send", "text"));
+        };
+        let bytes = crate::pty::unescape(text)
+            .map_err(|e| ScriptError::new(ErrorKind::SyntaxError, format!("send: {}", e)))?;
+        session(state, "send")?.send(&bytes).map_err(|e| {
+            ScriptError::new(ErrorKind::CommandFailed, format!("send: {}", e))
+        })?;
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Type text into the pty (\\n, \\r, \\t, \\e, \\xHH escapes)".into(),
+            args: "text".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
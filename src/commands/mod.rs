@@ -4,6 +4,7 @@
 //! for pattern matching (always regex), env display, and file operations.
 
 mod exec;
+mod interact;
 mod output;
 mod cmp;
 mod file_ops;
@@ -11,22 +12,30 @@ mod flow;
 mod env;
 mod text;
 mod help;
+mod net;
 
 use std::collections::HashMap;
-use crate::engine::BoxedCmd;
+use crate::engine::{BoxedCmd, BoxedWrapCmd};
 
 pub use help::HelpCmd;
+pub use flow::parse_go_duration;
 
 /// Return the default set of built-in commands
 pub fn default_commands() -> HashMap<String, BoxedCmd> {
     let mut cmds: HashMap<String, BoxedCmd> = HashMap::new();
     cmds.insert("exec".into(), Box::new(exec::ExecCmd));
+    cmds.insert("stdin".into(), Box::new(exec::StdinCmd));
+    cmds.insert("exitcode".into(), Box::new(exec::ExitCodeCmd));
+    cmds.insert("expect".into(), Box::new(interact::ExpectCmd));
+    cmds.insert("send".into(), Box::new(interact::SendCmd));
     cmds.insert("stdout".into(), Box::new(output::StdoutCmd));
     cmds.insert("stderr".into(), Box::new(output::StderrCmd));
     cmds.insert("cmp".into(), Box::new(cmp::CmpCmd));
     cmds.insert("cmpenv".into(), Box::new(cmp::CmpEnvCmd));
+    cmds.insert("cmpjson".into(), Box::new(cmp::CmpJsonCmd));
     cmds.insert("exists".into(), Box::new(file_ops::ExistsCmd));
     cmds.insert("grep".into(), Box::new(output::GrepCmd));
+    cmds.insert("capture".into(), Box::new(output::CaptureCmd));
     cmds.insert("cat".into(), Box::new(file_ops::CatCmd));
     cmds.insert("cd".into(), Box::new(env::CdCmd));
     cmds.insert("cp".into(), Box::new(file_ops::CpCmd));
@@ -42,5 +51,16 @@ pub fn default_commands() -> HashMap<String, BoxedCmd> {
     cmds.insert("symlink".into(), Box::new(file_ops::SymlinkCmd));
     cmds.insert("sleep".into(), Box::new(flow::SleepCmd));
     cmds.insert("wait".into(), Box::new(flow::WaitCmd));
+    cmds.insert("kill".into(), Box::new(flow::KillCmd));
+    cmds.insert("waitfor".into(), Box::new(net::WaitForCmd));
+    cmds.insert("freeport".into(), Box::new(net::FreePortCmd));
+    cmds.insert("httpserve".into(), Box::new(net::HttpServeCmd));
+    cmds
+}
+
+/// Return the default set of built-in wrapper commands
+pub fn default_wrappers() -> HashMap<String, BoxedWrapCmd> {
+    let mut cmds: HashMap<String, BoxedWrapCmd> = HashMap::new();
+    cmds.insert("eventually".into(), Box::new(flow::EventuallyCmd));
     cmds
 }
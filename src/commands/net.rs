@@ -0,0 +1,233 @@
+//! waitfor — wait until a server or file is ready; freeport — pick unused
+//! ports for servers to listen on; httpserve — serve canned HTTP responses
+
+use std::time::{Duration, Instant};
+use crate::engine::{Cmd, CmdContext, CmdResult, CmdUsage};
+use crate::error::{ScriptError, ErrorKind};
+use crate::state::State;
+use super::flow::{parse_go_duration, set_job_name};
+
+// ──────────────────────────────────────────────────────────
+// waitfor — poll until targets are connectable or exist
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct WaitForCmd;
+
+const WAITFOR_ARGS: &str = "[-timeout=duration] tcp:host:port|unix:path|file:path...";
+
+/// How long `waitfor` polls unless given `-timeout=`
+const WAITFOR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between `waitfor` polls
+const WAITFOR_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Something `waitfor` can wait for
+enum Target {
+    Tcp(String),
+    Unix(std::path::PathBuf),
+    File(std::path::PathBuf),
+}
+
+impl Target {
+    fn parse(state: &State, spec: &str) -> Option<Self> {
+        let (scheme, rest) = spec.split_once(':')?;
+        if rest.is_empty() {
+            return None;
+        }
+        match scheme {
+            "tcp" => Some(Target::Tcp(rest.to_string())),
+            "unix" => Some(Target::Unix(state.resolve_path(rest))),
+            "file" => Some(Target::File(state.resolve_path(rest))),
+            _ => None,
+        }
+    }
+
+    /// Check once; the error says why the target isn't ready yet
+    fn probe(&self, deadline: Instant) -> Result<(), String> {
+        match self {
+            Target::Tcp(addr) => {
+                use std::net::ToSocketAddrs;
+                let addrs = addr.to_socket_addrs().map_err(|e| e.to_string())?;
+                let mut last = format!("{}: no addresses", addr);
+                for addr in addrs {
+                    // Don't let one attempt run past the deadline
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let timeout = remaining.clamp(Duration::from_millis(1), Duration::from_secs(1));
+                    match std::net::TcpStream::connect_timeout(&addr, timeout) {
+                        Ok(_) => return Ok(()),
+                        Err(e) => last = e.to_string(),
+                    }
+                }
+                Err(last)
+            }
+            #[cfg(unix)]
+            Target::Unix(path) => std::os::unix::net::UnixStream::connect(path)
+                .map(drop)
+                .map_err(|e| e.to_string()),
+            #[cfg(not(unix))]
+            Target::Unix(_) => Err("unix sockets are not supported on this platform".into()),
+            Target::File(path) if path.exists() => Ok(()),
+            Target::File(_) => Err("no such file".into()),
+        }
+    }
+}
+
+impl Cmd for WaitForCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let mut timeout = WAITFOR_TIMEOUT;
+        let mut args = args;
+        if let Some(value) = args.first().and_then(|a| a.strip_prefix("-timeout=")) {
+            timeout = parse_go_duration(value)
+                .map_err(|e| ScriptError::new(ErrorKind::SyntaxError, format!("waitfor: {}", e)))?;
+            args = &args[1..];
+        }
+        if args.is_empty() {
+            return Err(ScriptError::usage("waitfor", WAITFOR_ARGS));
+        }
+        let targets = args
+            .iter()
+            .map(|spec| {
+                Target::parse(state, spec).map(|t| (spec, t)).ok_or_else(|| {
+                    ScriptError::new(ErrorKind::UsageError, format!(
+                        "waitfor: invalid target {:?} (want tcp:host:port, unix:path or file:path)", spec))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut deadline = Instant::now() + timeout;
+        if let Some(script_deadline) = state.deadline {
+            deadline = deadline.min(script_deadline);
+        }
+        for (spec, target) in targets {
+            loop {
+                let err = match target.probe(deadline) {
+                    Ok(()) => break,
+                    Err(e) => e,
+                };
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(ScriptError::new(ErrorKind::Timeout, format!(
+                        "waitfor: {} not ready after {:?}: {}", spec, timeout, err)));
+                }
+                std::thread::sleep(WAITFOR_INTERVAL.min(deadline - now));
+            }
+            state.logf(&format!("{} is ready", spec));
+        }
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Wait until TCP ports or unix sockets accept connections, or files exist".into(),
+            args: WAITFOR_ARGS.into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// httpserve — canned-response HTTP server in the background
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct HttpServeCmd;
+
+const HTTPSERVE_ARGS: &str = "[-port=N] [-log=file] [-name=job] routes";
+
+impl Cmd for HttpServeCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        self.run_with(state, args, &mut CmdContext::default())
+    }
+
+    fn run_with(&self, state: &mut State, args: &[String], ctx: &mut CmdContext) -> Result<CmdResult, ScriptError> {
+        let mut port = 0;
+        let mut log = "httpserve.log";
+        let mut args = args;
+        while let Some(opt) = args.first() {
+            if let Some(value) = opt.strip_prefix("-port=") {
+                port = value.parse().map_err(|_| {
+                    ScriptError::new(ErrorKind::UsageError, format!("httpserve: invalid port: {}", value))
+                })?;
+            } else if let Some(file) = opt.strip_prefix("-log=") {
+                log = file;
+            } else if let Some(name) = opt.strip_prefix("-name=") {
+                set_job_name(state, ctx, "httpserve", name)?;
+            } else {
+                break;
+            }
+            args = &args[1..];
+        }
+        let [routes_file] = args else {
+            return Err(ScriptError::usage("httpserve", HTTPSERVE_ARGS));
+        };
+
+        let text = state.read_file(routes_file).map_err(|e| {
+            ScriptError::new(ErrorKind::FileNotFound, format!("httpserve: {}: {}", routes_file, e))
+        })?;
+        let routes = crate::http::parse_routes(&text, |name| state.read_file(name).map_err(|e| e.to_string()))
+            .map_err(|e| ScriptError::syntax(format!("httpserve: {}: {}", routes_file, e)))?;
+
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port)).map_err(|e| {
+            ScriptError::new(ErrorKind::CommandFailed, format!("httpserve: listening on 127.0.0.1:{}: {}", port, e))
+        })?;
+        let port = listener.local_addr().map_err(|e| ScriptError::new(ErrorKind::Io, format!("httpserve: {}", e)))?.port();
+        let server = crate::http::Server::start(listener, routes, &state.resolve_path(log))
+            .map_err(|e| ScriptError::new(ErrorKind::Io, format!("httpserve: {}: {}", log, e)))?;
+
+        state.logf(&format!("serving on 127.0.0.1:{}", port));
+        state.setenv("HTTPSERVE_PORT", port.to_string());
+        Ok(CmdResult::Background(crate::engine::WaitHandle::Http(server)))
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Serve canned HTTP responses on localhost until wait or kill".into(),
+            args: HTTPSERVE_ARGS.into(),
+            regexp_args: None,
+            async_: true,
+            creates_files: true,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// freeport — set variables to unused localhost ports
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct FreePortCmd;
+
+impl Cmd for FreePortCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        if args.is_empty() || args.iter().any(|name| name.is_empty() || name.contains('=')) {
+            return Err(ScriptError::usage("freeport", "name..."));
+        }
+
+        // Let the OS pick each port; hold every listener until all are
+        // picked so the names get distinct ports, then release them
+        let listeners = args
+            .iter()
+            .map(|_| std::net::TcpListener::bind(("127.0.0.1", 0)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ScriptError::new(ErrorKind::Io, format!("freeport: {}", e)))?;
+        for (name, listener) in args.iter().zip(&listeners) {
+            let port = listener
+                .local_addr()
+                .map_err(|e| ScriptError::new(ErrorKind::Io, format!("freeport: {}", e)))?
+                .port();
+            state.logf(&format!("{}={}", name, port));
+            state.setenv(name, port.to_string());
+        }
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Set environment variables to unused localhost TCP ports".into(),
+            args: "name...".into(),
+            regexp_args: None,
+            async_: false,
+            creates_files: false,
+        }
+    }
+}
@@ -1,4 +1,4 @@
-//! stdout / stderr / grep — output pattern matching commands
+//! stdout / stderr / grep / capture — output pattern matching commands
 
 use crate::engine::{Cmd, CmdResult, CmdUsage, first_non_flag};
 use crate::error::{ScriptError, ErrorKind};
@@ -12,7 +12,7 @@ pub(super) struct StdoutCmd;
 
 impl Cmd for StdoutCmd {
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
-        let content = state.stdout.clone();
+        let content = state.stdout_text().into_owned();
         match_output("stdout", &content, args, state)
     }
 
@@ -22,6 +22,7 @@ impl Cmd for StdoutCmd {
             args: "[-count=N] [-q] pattern".into(),
             regexp_args: Some(first_non_flag),
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -34,7 +35,7 @@ pub(super) struct StderrCmd;
 
 impl Cmd for StderrCmd {
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
-        let content = state.stderr.clone();
+        let content = state.stderr_text().into_owned();
         match_output("stderr", &content, args, state)
     }
 
@@ -44,6 +45,7 @@ impl Cmd for StderrCmd {
             args: "[-count=N] [-q] pattern".into(),
             regexp_args: Some(first_non_flag),
             async_: false,
+            creates_files: false,
         }
     }
 }
@@ -127,6 +129,52 @@ impl Cmd for GrepCmd {
             args: "[-count=N] [-q] pattern file".into(),
             regexp_args: Some(first_non_flag),
             async_: false,
+            creates_files: false,
+        }
+    }
+}
+
+// ──────────────────────────────────────────────────────────
+// capture — set an environment variable from a regex match
+// ──────────────────────────────────────────────────────────
+
+pub(super) struct CaptureCmd;
+
+impl Cmd for CaptureCmd {
+    fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError> {
+        let [name, file, pattern] = args else {
+            return Err(ScriptError::usage("capture", "name file pattern"));
+        };
+        if name.is_empty() || name.contains('=') {
+            return Err(ScriptError::usage("capture", "name file pattern"));
+        }
+
+        let content = state.read_file(file).map_err(|e| {
+            ScriptError::new(ErrorKind::FileNotFound, format!("capture: {}: {}", file, e))
+        })?;
+        let re = compile_regex("capture", pattern)?;
+
+        // The first group, or the whole match for a pattern without groups
+        let caps = re.captures(&content).ok_or_else(|| {
+            ScriptError::new(
+                ErrorKind::PatternMismatch,
+                format!("capture: no match for pattern /{}/ in {}\ncontent:\n{}", pattern, file, content),
+            )
+        })?;
+        let value = caps.get(1).or_else(|| caps.get(0)).map_or("", |m| m.as_str()).to_string();
+
+        state.logf(&format!("{}={}", name, value));
+        state.setenv(name, value);
+        Ok(CmdResult::Ok)
+    }
+
+    fn usage(&self) -> CmdUsage {
+        CmdUsage {
+            summary: "Set an environment variable from a regex match in a file".into(),
+            args: "name file pattern".into(),
+            regexp_args: Some(|_| vec![2]),
+            async_: false,
+            creates_files: false,
         }
     }
 }
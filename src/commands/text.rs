@@ -50,6 +50,7 @@ impl Cmd for ReplaceCmd {
             args: "[old new]... file".into(),
             regexp_args: None,
             async_: false,
+            creates_files: false,
         }
     }
 }
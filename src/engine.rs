@@ -3,10 +3,16 @@
 //! The Engine holds command and condition registries.
 //! It is stateless config — one engine can run many scripts.
 
-use crate::error::ScriptError;
-use crate::parser::ArgFragment;
-use crate::state::State;
+use crate::ast::{CommandStatement, FuncDef, Script, Statement, StatementKind};
+use crate::error::{ErrorKind, ScriptError};
+use crate::lint::Diagnostic;
+use crate::parser::{ArgFragment, CondExpr, ParseError, ScriptCondition, ScriptLine};
+use crate::state::{ScriptFunc, State};
 use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Result returned by a command execution
 pub enum CmdResult {
@@ -25,36 +31,265 @@ pub enum CmdResult {
 /// This generalizes the Go `WaitFunc` pattern: both subprocess (`exec`) and
 /// thread-based (`sleep`) async commands return a `WaitHandle`.
 pub enum WaitHandle {
-    /// A running subprocess (exec)
-    Process(std::process::Child),
+    /// A running subprocess (exec), with an optional per-command deadline
+    Process(std::process::Child, Option<Instant>),
+    /// The stages of an `exec a | exec b` pipeline, first to last, each
+    /// one's stdout feeding the next one's stdin
+    Pipeline(Vec<std::process::Child>, Option<Instant>),
+    /// A subprocess on a pseudo-terminal (`exec -pty`); its stdout is the
+    /// terminal transcript
+    Pty(std::process::Child, Arc<crate::pty::Transcript>, Option<Instant>),
+    /// A canned-response HTTP server (`httpserve`); waiting stops it, and
+    /// its stdout is the request log
+    Http(crate::http::Server),
     /// A running thread (sleep) — the JoinHandle returns (stdout, stderr, error).
     Thread(std::thread::JoinHandle<Result<(), String>>),
 }
 
+/// Outcome of waiting on a `WaitHandle`
+#[derive(Debug, Default)]
+pub struct WaitOutput {
+    /// Captured stdout bytes (partial if the operation timed out)
+    pub stdout: Vec<u8>,
+    /// Captured stderr bytes (partial if the operation timed out)
+    pub stderr: Vec<u8>,
+    /// Error message if the operation failed
+    pub error: Option<String>,
+    /// Exit status of a subprocess that exited normally
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the subprocess (unix only)
+    pub signal: Option<i32>,
+    /// Whether the operation was abandoned (and killed) at its deadline
+    pub timed_out: bool,
+    /// Exit status of each subprocess, first to last (one for a plain
+    /// `exec`, one per stage of a pipeline)
+    pub stages: Vec<StageStatus>,
+}
+
+/// How one subprocess of a `WaitHandle` ended
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageStatus {
+    /// Exit status, if it exited normally
+    pub exit_code: Option<i32>,
+    /// Signal that terminated it (unix only)
+    pub signal: Option<i32>,
+}
+
+/// How long to keep draining pipes after killing a timed-out process.
+/// A grandchild may still hold the pipes open; we don't wait for it.
+const KILL_GRACE: Duration = Duration::from_millis(100);
+
+/// Poll interval while waiting for a deadline
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How deeply `func` calls may nest, so runaway recursion fails cleanly
+const MAX_CALL_DEPTH: usize = 100;
+
 impl WaitHandle {
     /// Wait for the async operation to complete.
-    /// Returns (stdout, stderr, Option<error_message>).
-    pub fn wait(self) -> (String, String, Option<String>) {
+    ///
+    /// `deadline` is the script-level deadline; a process handle's own
+    /// deadline (from `exec -timeout=`) applies too, whichever is earlier.
+    /// On expiry the subprocess is killed and whatever output was captured
+    /// so far is returned with `timed_out` set.
+    pub fn wait(self, deadline: Option<Instant>) -> WaitOutput {
         match self {
-            WaitHandle::Process(child) => match child.wait_with_output() {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    let err = if output.status.success() {
-                        None
-                    } else {
-                        Some(format!("exit code {}", output.status.code().unwrap_or(-1)))
-                    };
-                    (stdout, stderr, err)
-                }
-                Err(e) => (String::new(), String::new(), Some(e.to_string())),
+            WaitHandle::Process(child, own) => wait_processes(vec![child], earliest(own, deadline)),
+            WaitHandle::Pipeline(children, own) => wait_processes(children, earliest(own, deadline)),
+            WaitHandle::Pty(child, transcript, own) => {
+                let mut output = wait_processes(vec![child], earliest(own, deadline));
+                output.stdout = transcript.finish();
+                output
+            }
+            WaitHandle::Http(server) => match server.finish() {
+                Ok(requests) => WaitOutput { stdout: requests, ..Default::default() },
+                Err(e) => WaitOutput { error: Some(e), ..Default::default() },
             },
-            WaitHandle::Thread(handle) => match handle.join() {
-                Ok(Ok(())) => (String::new(), String::new(), None),
-                Ok(Err(e)) => (String::new(), String::new(), Some(e)),
-                Err(_) => (String::new(), String::new(), Some("thread panicked".into())),
+            WaitHandle::Thread(handle) => {
+                if let Some(deadline) = deadline {
+                    while !handle.is_finished() {
+                        if Instant::now() >= deadline {
+                            // Threads can't be killed; leave it detached
+                            return WaitOutput {
+                                error: Some("timed out".into()),
+                                timed_out: true,
+                                ..Default::default()
+                            };
+                        }
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                }
+                let error = match handle.join() {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e),
+                    Err(_) => Some("thread panicked".into()),
+                };
+                WaitOutput { error, ..Default::default() }
+            }
+        }
+    }
+
+    /// Send a signal to the async operation without waiting for it; a
+    /// pipeline signals every stage, and any signal stops a server.
+    /// Threads can't be signalled.
+    pub fn signal(&mut self, sig: i32) -> std::io::Result<()> {
+        match self {
+            WaitHandle::Process(child, _) | WaitHandle::Pty(child, _, _) => crate::signal::send(child, sig),
+            WaitHandle::Pipeline(children, _) => {
+                // Signal every stage (one that exited may have left children
+                // in its group); report the first failure
+                let mut result = Ok(());
+                for child in children.iter_mut() {
+                    if let Err(e) = crate::signal::send(child, sig) {
+                        result = result.and(Err(e));
+                    }
+                }
+                result
+            }
+            WaitHandle::Http(server) => {
+                server.stop();
+                Ok(())
+            }
+            WaitHandle::Thread(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "only processes can be signalled",
+            )),
+        }
+    }
+}
+
+/// The earlier of two optional deadlines
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Wait for a subprocess, or for every stage of a pipeline, draining
+/// stdout/stderr on reader threads so the output captured so far survives
+/// a kill. All pipes are drained from the start, so no stage can block on
+/// a full stderr pipe while an earlier one is waited for.
+///
+/// Output is the last stage's stdout and every stage's stderr, in stage
+/// order. Like `set -o pipefail`, the status is that of the last stage
+/// that failed, or of the last stage if all succeeded.
+fn wait_processes(mut children: Vec<std::process::Child>, deadline: Option<Instant>) -> WaitOutput {
+    let stdout = children.last_mut().and_then(|c| c.stdout.take()).map(PipeReader::spawn);
+    let stderrs: Vec<_> = children.iter_mut().map(|c| c.stderr.take().map(PipeReader::spawn)).collect();
+
+    let mut timed_out = false;
+    let mut statuses = Vec::with_capacity(children.len());
+    for i in 0..children.len() {
+        let status = match deadline {
+            None => children[i].wait(),
+            Some(deadline) => loop {
+                match children[i].try_wait() {
+                    Ok(Some(status)) => break Ok(status),
+                    Ok(None) if Instant::now() >= deadline => {
+                        timed_out = true;
+                        for child in &mut children[i..] {
+                            let _ = crate::signal::send(child, crate::signal::SIGKILL);
+                        }
+                        break children[i].wait();
+                    }
+                    Ok(None) => std::thread::sleep(POLL_INTERVAL),
+                    Err(e) => break Err(e),
+                }
             },
+        };
+        statuses.push(status);
+    }
+
+    let grace = if timed_out { Some(KILL_GRACE) } else { None };
+    let stdout = stdout.map(|r| r.finish(grace)).unwrap_or_default();
+    let mut stderr = Vec::new();
+    for reader in stderrs.into_iter().flatten() {
+        stderr.extend(reader.finish(grace));
+    }
+
+    let stages: Vec<StageStatus> = statuses
+        .iter()
+        .map(|status| StageStatus {
+            exit_code: status.as_ref().ok().and_then(|s| s.code()),
+            #[cfg(unix)]
+            signal: status.as_ref().ok().and_then(std::os::unix::process::ExitStatusExt::signal),
+            #[cfg(not(unix))]
+            signal: None,
+        })
+        .collect();
+    let failed = statuses.iter().rposition(|s| !matches!(s, Ok(status) if status.success()));
+    let index = failed.unwrap_or(stages.len().saturating_sub(1));
+    let StageStatus { exit_code, signal } = stages.get(index).cloned().unwrap_or_default();
+
+    let error = if timed_out {
+        Some("timed out".to_string())
+    } else {
+        failed.map(|i| {
+            let error = match &statuses[i] {
+                Ok(_) if signal.is_some() => {
+                    format!("killed by {}", crate::signal::name(signal.unwrap_or_default()))
+                }
+                Ok(status) => format!("exit code {}", status.code().unwrap_or(-1)),
+                Err(e) => e.to_string(),
+            };
+            if stages.len() > 1 {
+                format!("{} (pipeline stage {} of {})", error, i + 1, stages.len())
+            } else {
+                error
+            }
+        })
+    };
+
+    WaitOutput {
+        stdout,
+        stderr,
+        error,
+        exit_code,
+        signal,
+        timed_out,
+        stages,
+    }
+}
+
+/// Drains a pipe on a background thread into a shared buffer.
+struct PipeReader {
+    buf: Arc<Mutex<Vec<u8>>>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl PipeReader {
+    fn spawn(mut pipe: impl Read + Send + 'static) -> Self {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&buf);
+        let thread = std::thread::spawn(move || {
+            let mut chunk = [0u8; 8192];
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => sink.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(&chunk[..n]),
+                }
+            }
+        });
+        Self { buf, thread }
+    }
+
+    /// Collect the output. With a `grace` period, give up waiting for EOF
+    /// after that long and return what has been read so far.
+    fn finish(self, grace: Option<Duration>) -> Vec<u8> {
+        match grace {
+            None => {
+                let _ = self.thread.join();
+            }
+            Some(grace) => {
+                let until = Instant::now() + grace;
+                while !self.thread.is_finished() && Instant::now() < until {
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
         }
+        let mut buf = self.buf.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut *buf)
     }
 }
 
@@ -72,6 +307,23 @@ pub struct CmdUsage {
     /// Whether this command can be run in the background with `&`.
     /// Only `exec` and `sleep` should set this to true.
     pub async_: bool,
+    /// Whether this command may create files in the workdir. Static checks
+    /// stop looking for missing archive files after a line that runs one.
+    pub creates_files: bool,
+}
+
+/// What the engine knows about a command's script line beyond its
+/// arguments, and what the command reports back about it
+#[derive(Debug, Clone, Default)]
+pub struct CmdContext {
+    /// Whether the line ends with `&`
+    pub background: bool,
+    /// Indices in the expanded arguments of the `|` arguments that
+    /// separate `exec` pipeline stages
+    pub pipes: Vec<usize>,
+    /// Name of the background job the command starts (`-name=`); the
+    /// engine attaches it to the background entry
+    pub job: Option<String>,
 }
 
 /// A command that can be executed in a script
@@ -79,6 +331,14 @@ pub trait Cmd: Send + Sync {
     /// Execute the command
     fn run(&self, state: &mut State, args: &[String]) -> Result<CmdResult, ScriptError>;
 
+    /// Execute the command as part of a script line described by `ctx`.
+    /// Commands that don't need the line (most of them) keep the default,
+    /// which calls `run`.
+    fn run_with(&self, state: &mut State, args: &[String], ctx: &mut CmdContext) -> Result<CmdResult, ScriptError> {
+        let _ = ctx;
+        self.run(state, args)
+    }
+
     /// Return usage information
     fn usage(&self) -> CmdUsage;
 }
@@ -86,10 +346,39 @@ pub trait Cmd: Send + Sync {
 /// A boxed command
 pub type BoxedCmd = Box<dyn Cmd>;
 
+/// A command that runs another command, such as `eventually`.
+///
+/// Its leading arguments are its own options; the rest are the command
+/// line it wraps (any command or `func`). The engine checks the line's
+/// conditions and applies its `!`/`?` to the overall outcome; the wrapper
+/// decides how often the wrapped command runs.
+pub trait WrapCmd: Send + Sync {
+    /// How many of the leading raw (unexpanded, joined) arguments are the
+    /// wrapper's own options
+    fn options(&self, raw_args: &[String]) -> usize;
+
+    /// Execute the wrapper with its expanded options; each call of `inner`
+    /// runs the wrapped command once
+    fn run(
+        &self,
+        state: &mut State,
+        options: &[String],
+        inner: &mut dyn FnMut(&mut State) -> Result<(), ScriptError>,
+    ) -> Result<(), ScriptError>;
+
+    /// Return usage information
+    fn usage(&self) -> CmdUsage;
+}
+
+/// A boxed wrapper command
+pub type BoxedWrapCmd = Box<dyn WrapCmd>;
+
 /// The script engine — holds command and condition registries
 pub struct Engine {
     /// Registered commands
     pub commands: HashMap<String, BoxedCmd>,
+    /// Registered commands that wrap another command
+    pub wrappers: HashMap<String, BoxedWrapCmd>,
     /// Registered conditions
     pub conditions: HashMap<String, crate::conditions::BoxedCondition>,
     /// Whether to suppress command logging
@@ -99,38 +388,70 @@ pub struct Engine {
 impl Engine {
     /// Create a new engine with default commands and conditions
     pub fn new() -> Self {
-        let mut commands = crate::commands::default_commands();
-        let conditions = crate::conditions::default_conditions();
+        let mut engine = Self {
+            commands: crate::commands::default_commands(),
+            wrappers: crate::commands::default_wrappers(),
+            conditions: crate::conditions::default_conditions(),
+            quiet: false,
+        };
 
         // Build the help command with knowledge of all registered commands/conditions.
-        // Must be done after populating both registries.
-        let mut cmd_help: Vec<(String, String, String)> = commands
-            .iter()
-            .map(|(name, cmd)| {
-                let u = cmd.usage();
-                (name.clone(), u.args.clone(), u.summary.clone())
-            })
+        // Must be done after populating the registries.
+        let cmd_help: Vec<(String, String, String)> = engine
+            .usages()
+            .into_iter()
+            .map(|(name, u)| (name.to_string(), u.args, u.summary))
             .collect();
-        cmd_help.sort_by(|a, b| a.0.cmp(&b.0));
 
-        let mut cond_help: Vec<String> = conditions.keys().cloned().collect();
+        let mut cond_help: Vec<String> = engine.conditions.keys().cloned().collect();
         cond_help.sort();
 
-        commands.insert(
+        engine.commands.insert(
             "help".into(),
             Box::new(crate::commands::HelpCmd::new(cmd_help, cond_help)),
         );
+        engine
+    }
 
-        Self {
-            commands,
-            conditions,
-            quiet: false,
+    /// Register a custom command, replacing any command or wrapper of
+    /// that name
+    pub fn register_command(&mut self, name: impl Into<String>, cmd: BoxedCmd) {
+        let name = name.into();
+        self.wrappers.remove(&name);
+        self.commands.insert(name, cmd);
+    }
+
+    /// Register a custom wrapper command, replacing any command or
+    /// wrapper of that name
+    pub fn register_wrapper(&mut self, name: impl Into<String>, cmd: BoxedWrapCmd) {
+        let name = name.into();
+        self.commands.remove(&name);
+        self.wrappers.insert(name, cmd);
+    }
+
+    /// Whether `name` is a registered command or wrapper
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands.contains_key(name) || self.wrappers.contains_key(name)
+    }
+
+    /// Usage of the command or wrapper `name`
+    pub fn usage(&self, name: &str) -> Option<CmdUsage> {
+        match self.commands.get(name) {
+            Some(cmd) => Some(cmd.usage()),
+            None => self.wrappers.get(name).map(|cmd| cmd.usage()),
         }
     }
 
-    /// Register a custom command
-    pub fn register_command(&mut self, name: impl Into<String>, cmd: BoxedCmd) {
-        self.commands.insert(name.into(), cmd);
+    /// Names and usage of every registered command and wrapper, by name
+    pub fn usages(&self) -> Vec<(&str, CmdUsage)> {
+        let mut usages: Vec<(&str, CmdUsage)> = self
+            .commands
+            .iter()
+            .map(|(name, cmd)| (name.as_str(), cmd.usage()))
+            .chain(self.wrappers.iter().map(|(name, cmd)| (name.as_str(), cmd.usage())))
+            .collect();
+        usages.sort_by(|a, b| a.0.cmp(b.0));
+        usages
     }
 
     /// Register a custom condition
@@ -143,144 +464,320 @@ impl Engine {
     }
 
     /// Execute a script from text (the comment section of a txtar archive)
+    ///
+    /// The whole script is parsed first; syntax errors are all reported
+    /// before anything runs.
     pub fn execute(
         &self,
         state: &mut State,
         script: &str,
         filename: &str,
     ) -> Result<(), ScriptError> {
-        // First pass: merge continuation lines
-        let merged_lines = self.merge_continuation_lines(script.lines().collect());
+        let script = crate::ast::parse_script(script)
+            .map_err(|errors| parse_errors_to_script_error(&errors, filename))?;
+        self.execute_script(state, &script, filename)
+    }
 
-        let mut line_idx = 0;
+    /// Execute a parsed script
+    pub fn execute_script(
+        &self,
+        state: &mut State,
+        script: &Script,
+        filename: &str,
+    ) -> Result<(), ScriptError> {
+        state.push_include(crate::include::canonical(Path::new(filename)));
+        let result = self.execute_statements(state, &script.statements, Source { filename, top_level: true });
+        state.pop_include();
+        result?;
+        Ok(())
+    }
 
-        while line_idx < merged_lines.len() {
-            let line = &merged_lines[line_idx];
-            let line_number = line_idx + 1;
-            line_idx += 1;
+    /// Run a list of statements, descending into blocks and includes
+    fn execute_statements(
+        &self,
+        state: &mut State,
+        statements: &[Statement],
+        source: Source<'_>,
+    ) -> Result<Flow, ScriptError> {
+        let filename = source.filename;
+        for stmt in statements {
+            let line_number = stmt.line_number();
 
-            // Lines starting with # are section comments — log and skip.
-            // Go-compatible: only lines where '#' is the very first character
-            // (no leading whitespace) are section comments.
-            if line.starts_with('#') {
-                if !self.quiet {
-                    state.logf(&format!("{}", line.trim()));
-                }
-                continue;
+            if state.deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(ScriptError::new(ErrorKind::Timeout, "script timed out")
+                    .with_location(filename, line_number));
             }
 
-            let parsed = match crate::parser::parse_line(line, line_number) {
-                Ok(Some(p)) => p,
-                Ok(None) => continue, // blank line
-                Err(e) => {
-                    return Err(ScriptError::syntax(e.message).with_location(filename, line_number));
+            match &stmt.kind {
+                StatementKind::Blank => {}
+                // Section comments are logged; indented comments are not.
+                // Go-compatible: only lines where '#' is the very first character
+                // (no leading whitespace) are section comments.
+                StatementKind::Comment { text, section } => {
+                    if *section && !self.quiet {
+                        state.logf(&format!("#{}", text));
+                    }
                 }
-            };
-
-            // Check for heredoc syntax in arguments (<<MARKER)
-            let mut expanded_raw_args = parsed.raw_args.clone();
-            let mut heredoc_log_lines: Vec<String> = Vec::new();
-
-            for arg_frags in &mut expanded_raw_args {
-                for frag in arg_frags.iter_mut() {
-                    if frag.s.starts_with("<<") && !frag.quoted {
-                        let marker = &frag.s[2..]; // Remove "<<"
-
-                        // Collect heredoc content
-                        let mut heredoc_content = String::new();
-
-                        while line_idx < merged_lines.len() {
-                            let heredoc_line = &merged_lines[line_idx];
-                            line_idx += 1;
-
-                            if heredoc_line.trim() == marker {
-                                // Found end marker
-                                break;
-                            }
+                StatementKind::Command(cmd) => {
+                    if let Flow::Stop = self.execute_command(state, cmd, line_number, source)? {
+                        return Ok(Flow::Stop);
+                    }
+                }
+                StatementKind::If(block) => {
+                    if !self.quiet {
+                        state.logf(&format!("> {}", block.header.raw.trim()));
+                    }
+                    let mut met = true;
+                    for cond in &block.conditions {
+                        if !self.eval_condition(state, cond).map_err(|e| e.with_location(filename, line_number))? {
+                            met = false;
+                            break;
+                        }
+                    }
 
-                            if !heredoc_content.is_empty() {
-                                heredoc_content.push('\n');
-                            }
-                            heredoc_content.push_str(heredoc_line);
-                            heredoc_log_lines.push(heredoc_line.to_string());
+                    let branch = if met {
+                        &block.then_branch
+                    } else {
+                        state.logf("[condition not met]");
+                        if block.else_line.is_some() && !self.quiet {
+                            state.logf("> else");
                         }
+                        &block.else_branch
+                    };
+                    if let Flow::Stop = self.execute_statements(state, branch, source)? {
+                        return Ok(Flow::Stop);
+                    }
+                }
+                StatementKind::Func(func) => {
+                    if !self.quiet {
+                        state.logf(&format!("> {}", func.header.raw.trim()));
+                    }
+                    if self.has_command(&func.name) {
+                        return Err(ScriptError::syntax(format!("func {0}: {0} is already a command", func.name))
+                            .with_location(filename, line_number));
+                    }
+                    state.define_func(func.clone(), filename);
+                }
+                StatementKind::Include(include) => {
+                    if !self.quiet {
+                        state.logf(&format!("> {}", include.line.raw.trim()));
+                    }
 
-                        // Add trailing newline to match typical file/command output
-                        if !heredoc_content.is_empty() && !heredoc_content.ends_with('\n') {
-                            heredoc_content.push('\n');
+                    // Includes not loaded up front (see load_script) are loaded now
+                    let loaded;
+                    let (file, statements) = match &include.file {
+                        Some(file) => (file, &include.statements),
+                        None => {
+                            loaded = crate::include::read_include(include, filename, line_number, state.includes())?;
+                            state.extract_included_files(&loaded.files).map_err(|e| {
+                                ScriptError::new(ErrorKind::Io, format!("include {}: failed to extract files: {}", include.path, e))
+                                    .with_location(filename, line_number)
+                            })?;
+                            (&loaded.file, &loaded.statements)
                         }
+                    };
 
-                        // Store heredoc content and get virtual path
-                        let virtual_path = state.store_heredoc(heredoc_content);
-                        frag.s = virtual_path;
+                    let included = file.to_string_lossy();
+                    state.push_include(crate::include::canonical(file));
+                    let result =
+                        self.execute_statements(state, statements, Source { filename: &included, top_level: false });
+                    state.pop_include();
+                    let flow = result.map_err(|e| {
+                        if e.is_skip() || e.is_stop() {
+                            e
+                        } else {
+                            e.with_frame("included from", filename, line_number)
+                        }
+                    })?;
+                    if let Flow::Stop = flow {
+                        return Ok(Flow::Stop);
                     }
                 }
             }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    /// Run one command statement: conditions, heredocs, expansion, the
+    /// command itself and the `!`/`?`/`&` handling of its outcome.
+    fn execute_command(
+        &self,
+        state: &mut State,
+        stmt: &CommandStatement,
+        line_number: usize,
+        source: Source<'_>,
+    ) -> Result<Flow, ScriptError> {
+        let filename = source.filename;
+        let parsed = &stmt.line;
 
-            // Log the raw line
-            if !self.quiet {
-                state.logf(&format!("> {}", parsed.raw.trim()));
-                // Log heredoc content lines
-                for heredoc_line in &heredoc_log_lines {
+        // Heredoc arguments become virtual files holding their bodies.
+        // Only the top-level script's heredocs can be rewritten by update mode.
+        let mut expanded_raw_args = parsed.raw_args.clone();
+        for heredoc in &stmt.heredocs {
+            let virtual_path = if source.top_level {
+                state.store_heredoc_with_source(heredoc.body.clone(), &heredoc.marker, heredoc.lines.clone())
+            } else {
+                state.store_heredoc(heredoc.body.clone())
+            };
+            expanded_raw_args[heredoc.arg][heredoc.fragment].s = virtual_path;
+        }
+
+        // Log the raw line
+        if !self.quiet {
+            state.logf(&format!("> {}", parsed.raw.trim()));
+            // Log heredoc content lines
+            for heredoc in &stmt.heredocs {
+                for heredoc_line in heredoc.body.lines() {
                     state.logf(&format!("> {}", heredoc_line));
                 }
             }
+        }
 
-            // Evaluate conditions
-            let mut skip_line = false;
-            for cond in &parsed.conditions {
-                let result = self.eval_condition(state, cond)?;
-                if !result {
-                    skip_line = true;
-                    break;
-                }
-            }
-            if skip_line {
+        // Evaluate conditions
+        for cond in &parsed.conditions {
+            if !self.eval_condition(state, cond).map_err(|e| e.with_location(filename, line_number))? {
                 state.logf("[condition not met]");
-                continue;
+                return Ok(Flow::Continue);
             }
+        }
 
-            // Look up command
-            let cmd = self.commands.get(&parsed.command).ok_or_else(|| {
-                ScriptError::syntax(format!("unknown command: {}", parsed.command))
-                    .with_location(filename, line_number)
-            })?;
+        self.run_command(state, parsed, expanded_raw_args, line_number, filename)
+    }
 
-            // Determine which args are regexp (for QuoteMeta-style expansion)
-            let usage = cmd.usage();
-
-            // Validate background usage: only async commands can use &
-            if parsed.background && !usage.async_ {
-                return Err(ScriptError::new(
-                    crate::error::ErrorKind::SyntaxError,
-                    format!(
-                        "command {} does not support background execution (&)",
-                        parsed.command
-                    ),
-                )
-                .with_location(filename, line_number));
-            }
+    /// Run a command line whose conditions hold, with `raw_args` as its
+    /// arguments (heredocs already replaced by their virtual files)
+    fn run_command(
+        &self,
+        state: &mut State,
+        parsed: &ScriptLine,
+        expanded_raw_args: Vec<Vec<ArgFragment>>,
+        line_number: usize,
+        filename: &str,
+    ) -> Result<Flow, ScriptError> {
+        if let Some(wrapper) = self.wrappers.get(&parsed.command) {
+            return self.run_wrapper(state, wrapper.as_ref(), parsed, &expanded_raw_args, line_number, filename);
+        }
 
-            let regexp_arg_indices = if let Some(regexp_args_fn) = usage.regexp_args {
-                // Build raw (unexpanded, joined) args for the regexp_args function
-                let raw_joined: Vec<String> = expanded_raw_args
-                    .iter()
-                    .map(|frags| frags.iter().map(|f| f.s.as_str()).collect())
-                    .collect();
-                regexp_args_fn(&raw_joined)
-            } else {
-                Vec::new()
+        // Look up command, then the script's own macros
+        let Some(cmd) = self.commands.get(&parsed.command) else {
+            return match state.func(&parsed.command) {
+                Some(func) => self.call_func(state, parsed, &expanded_raw_args, &func, line_number, filename),
+                None => Err(ScriptError::syntax(format!("unknown command: {}", parsed.command))
+                    .with_location(filename, line_number)),
             };
+        };
+
+        // Determine which args are regexp (for QuoteMeta-style expansion)
+        let usage = cmd.usage();
+
+        // Validate background usage: only async commands can use &
+        if parsed.background && !usage.async_ {
+            return Err(ScriptError::new(
+                crate::error::ErrorKind::SyntaxError,
+                format!(
+                    "command {} does not support background execution (&)",
+                    parsed.command
+                ),
+            )
+            .with_location(filename, line_number));
+        }
+
+        let regexp_arg_indices = if let Some(regexp_args_fn) = usage.regexp_args {
+            // Build raw (unexpanded, joined) args for the regexp_args function
+            regexp_args_fn(&joined_args(&expanded_raw_args))
+        } else {
+            Vec::new()
+        };
+
+        // Expand arguments: fragment-aware, with regexp escaping for regex args
+        let expanded_args = expand_args(state, &expanded_raw_args, &regexp_arg_indices);
+
+        // Execute command
+        let mut ctx = CmdContext {
+            background: parsed.background,
+            pipes: expanded_positions(state, &expanded_raw_args, &parsed.pipes),
+            job: None,
+        };
+        let result = cmd.run_with(state, &expanded_args, &mut ctx);
+        let job = ctx.job;
+
+        match result {
+            Ok(CmdResult::Ok) => {
+                if parsed.negate {
+                    return Err(ScriptError::new(
+                        crate::error::ErrorKind::UnexpectedSuccess,
+                        format!("command succeeded unexpectedly: {}", parsed.raw.trim()),
+                    )
+                    .with_location(filename, line_number));
+                }
+            }
+            Ok(CmdResult::Stop(msg)) => {
+                state.logf(&format!("STOP: {}", msg));
+                return Ok(Flow::Stop);
+            }
+            Ok(CmdResult::Skip(msg)) => {
+                return Err(ScriptError::skip(msg).with_location(filename, line_number));
+            }
+            Ok(CmdResult::Background(handle)) => {
+                if parsed.background {
+                    // Push to background queue — will be harvested by `wait`
+                    state.background.push(crate::state::BackgroundCmd {
+                        handle,
+                        name: parsed.command.clone(),
+                        args: expanded_args,
+                        negate: parsed.negate,
+                        may_fail: parsed.may_fail,
+                        job,
+                        signaled: None,
+                    });
+                    // Go-compatible: clear stdout/stderr for background commands
+                    state.stdout.clear();
+                    state.stderr.clear();
+                } else {
+                    // Foreground: wait immediately via WaitHandle
+                    let output = handle.wait(state.deadline);
 
-            // Expand arguments: fragment-aware, with regexp escaping for regex args
-            let expanded_args = expand_args(state, &expanded_raw_args, &regexp_arg_indices);
+                    state.stdout = output.stdout;
+                    state.stderr = output.stderr;
+                    // exit_code only meaningful for Process handles
+                    state.set_exit_status(output.exit_code, output.signal);
+                    state.pipe_status = output.stages;
 
-            // Execute command
-            let result = cmd.run(state, &expanded_args);
+                    // Go-compatible: always log stdout/stderr (not gated by quiet)
+                    if !state.stdout.is_empty() {
+                        state.logf(&format!("[stdout]\n{}", state.stdout_text()));
+                    }
+                    if !state.stderr.is_empty() {
+                        state.logf(&format!("[stderr]\n{}", state.stderr_text()));
+                    }
 
-            match result {
-                Ok(CmdResult::Ok) => {
-                    if parsed.negate {
+                    // A timeout is fatal regardless of `!` / `?`
+                    if output.timed_out {
+                        return Err(ScriptError::new(
+                            ErrorKind::Timeout,
+                            output.error.unwrap_or_else(|| "timed out".into()),
+                        )
+                        .with_location(filename, line_number)
+                        .with_command(&parsed.command)
+                        .with_args(expanded_args.clone()));
+                    }
+
+                    if let Some(err_msg) = output.error {
+                        let err =
+                            ScriptError::new(crate::error::ErrorKind::CommandFailed, err_msg);
+                        if parsed.negate {
+                            // Go-compatible: log expected error as [<err>]
+                            state.logf(&format!("[{}]", err.message));
+                        } else if parsed.may_fail {
+                            state.logf(&format!("[{}]", err.message));
+                        } else {
+                            return Err(err
+                                .with_location(filename, line_number)
+                                .with_command(&parsed.command)
+                                .with_args(expanded_args.clone()));
+                        }
+                    } else if parsed.negate {
                         return Err(ScriptError::new(
                             crate::error::ErrorKind::UnexpectedSuccess,
                             format!("command succeeded unexpectedly: {}", parsed.raw.trim()),
@@ -288,157 +785,235 @@ impl Engine {
                         .with_location(filename, line_number));
                     }
                 }
-                Ok(CmdResult::Stop(msg)) => {
-                    state.logf(&format!("STOP: {}", msg));
-                    return Ok(());
-                }
-                Ok(CmdResult::Skip(msg)) => {
-                    return Err(ScriptError::skip(msg).with_location(filename, line_number));
-                }
-                Ok(CmdResult::Background(handle)) => {
-                    if parsed.background {
-                        // Push to background queue — will be harvested by `wait`
-                        state.background.push(crate::state::BackgroundCmd {
-                            handle,
-                            name: parsed.command.clone(),
-                            args: expanded_args,
-                            negate: parsed.negate,
-                            may_fail: parsed.may_fail,
-                        });
-                        // Go-compatible: clear stdout/stderr for background commands
-                        state.stdout = String::new();
-                        state.stderr = String::new();
-                    } else {
-                        // Foreground: wait immediately via WaitHandle
-                        let (stdout, stderr, err) = handle.wait();
-
-                        state.stdout = stdout;
-                        state.stderr = stderr;
-                        // exit_code only meaningful for Process handles
-                        state.exit_code = None;
-
-                        // Go-compatible: always log stdout/stderr (not gated by quiet)
-                        if !state.stdout.is_empty() {
-                            state.logf(&format!("[stdout]\n{}", state.stdout));
-                        }
-                        if !state.stderr.is_empty() {
-                            state.logf(&format!("[stderr]\n{}", state.stderr));
-                        }
-
-                        if let Some(err_msg) = err {
-                            let err =
-                                ScriptError::new(crate::error::ErrorKind::CommandFailed, err_msg);
-                            if parsed.negate {
-                                // Go-compatible: log expected error as [<err>]
-                                state.logf(&format!("[{}]", err.message));
-                            } else if parsed.may_fail {
-                                state.logf(&format!("[{}]", err.message));
-                            } else {
-                                return Err(err
-                                    .with_location(filename, line_number)
-                                    .with_command(&parsed.command)
-                                    .with_args(expanded_args.clone()));
-                            }
-                        } else if parsed.negate {
-                            return Err(ScriptError::new(
-                                crate::error::ErrorKind::UnexpectedSuccess,
-                                format!("command succeeded unexpectedly: {}", parsed.raw.trim()),
-                            )
-                            .with_location(filename, line_number));
-                        }
+            }
+            Err(e) => {
+                if parsed.negate {
+                    // Expected failure — continue
+                    if !self.quiet {
+                        state.logf(&format!("[expected failure: {}]", e.message));
                     }
-                }
-                Err(e) => {
-                    if parsed.negate {
-                        // Expected failure — continue
-                        if !self.quiet {
-                            state.logf(&format!("[expected failure: {}]", e.message));
-                        }
-                    } else if parsed.may_fail {
-                        // May fail — continue
-                        if !self.quiet {
-                            state.logf(&format!("[allowed failure: {}]", e.message));
-                        }
-                    } else {
-                        return Err(e
-                            .with_location(filename, line_number)
-                            .with_command(&parsed.command)
-                            .with_args(expanded_args.clone()));
+                } else if parsed.may_fail {
+                    // May fail — continue
+                    if !self.quiet {
+                        state.logf(&format!("[allowed failure: {}]", e.message));
                     }
+                } else {
+                    return Err(e
+                        .with_location(filename, line_number)
+                        .with_command(&parsed.command)
+                        .with_args(expanded_args.clone()));
                 }
             }
         }
 
-        Ok(())
+        Ok(Flow::Continue)
     }
 
-    /// Merge continuation lines in a script.
+    /// Run a call to a script-defined `func`: its body with the call's
+    /// arguments as `$1`, `$2`, ..., with `!`/`?` applied to the outcome.
     ///
-    /// Supports both Unix-style (\) and Windows-style (^) line continuation.
-    /// The continuation character must be the last non-whitespace character on the line.
-    fn merge_continuation_lines(&self, lines: Vec<&str>) -> Vec<String> {
-        let mut result = Vec::new();
-        let mut i = 0;
-
-        while i < lines.len() {
-            let mut current_line = lines[i].to_string();
+    /// A macro body runs once per call, so update mode never rewrites
+    /// its heredocs.
+    fn call_func(
+        &self,
+        state: &mut State,
+        parsed: &ScriptLine,
+        raw_args: &[Vec<ArgFragment>],
+        func: &ScriptFunc,
+        line_number: usize,
+        filename: &str,
+    ) -> Result<Flow, ScriptError> {
+        let at_call = |e: ScriptError| e.with_location(filename, line_number);
+        if parsed.background {
+            return Err(at_call(ScriptError::syntax(format!(
+                "command {} does not support background execution (&)",
+                parsed.command
+            ))));
+        }
+        let args = expand_args(state, raw_args, &[]);
+        if !func.def.accepts(args.len()) {
+            return Err(at_call(ScriptError::new(ErrorKind::UsageError, func.def.usage())));
+        }
+        if state.call_depth() >= MAX_CALL_DEPTH {
+            return Err(at_call(ScriptError::new(
+                ErrorKind::Other,
+                format!("func {}: calls nested more than {} deep", func.def.name, MAX_CALL_DEPTH),
+            )));
+        }
 
-            // Keep merging while the line ends with a continuation character
-            loop {
-                let trimmed = current_line.trim_end();
-
-                // Check if this line ends with a continuation character
-                let has_continuation = if trimmed.ends_with('\\') {
-                    // Remove the backslash
-                    current_line = trimmed[..trimmed.len() - 1].trim_end().to_string();
-                    true
-                } else if trimmed.ends_with('^') {
-                    // Remove the caret
-                    current_line = trimmed[..trimmed.len() - 1].trim_end().to_string();
-                    true
-                } else {
-                    false
-                };
+        state.push_call(args);
+        let result = self.execute_statements(state, &func.def.body, Source { filename: &func.file, top_level: false });
+        state.pop_call();
 
-                if !has_continuation {
-                    break;
+        match result {
+            Ok(Flow::Stop) => return Ok(Flow::Stop),
+            Ok(Flow::Continue) if parsed.negate => {
+                return Err(at_call(ScriptError::new(
+                    ErrorKind::UnexpectedSuccess,
+                    format!("command succeeded unexpectedly: {}", parsed.raw.trim()),
+                )));
+            }
+            Ok(Flow::Continue) => {}
+            Err(e) if e.is_skip() || e.is_stop() => return Err(e),
+            Err(e) if parsed.negate && e.kind != ErrorKind::Timeout => {
+                if !self.quiet {
+                    state.logf(&format!("[expected failure: {}]", e.message));
                 }
-
-                // Check if there's a next line to merge
-                i += 1;
-                if i >= lines.len() {
-                    break;
+            }
+            Err(e) if parsed.may_fail && e.kind != ErrorKind::Timeout => {
+                if !self.quiet {
+                    state.logf(&format!("[allowed failure: {}]", e.message));
                 }
+            }
+            Err(e) => return Err(e.with_frame("called from", filename, line_number)),
+        }
+        Ok(Flow::Continue)
+    }
 
-                // Append next line with a space separator
-                let next_line = lines[i].trim();
-                if !next_line.is_empty() {
-                    current_line.push(' ');
-                    current_line.push_str(next_line);
+    /// Run a line whose command is a wrapper: the wrapper decides how
+    /// often the wrapped command runs, and `!`/`?` apply to the outcome,
+    /// except to syntax and usage errors
+    fn run_wrapper(
+        &self,
+        state: &mut State,
+        wrapper: &dyn WrapCmd,
+        parsed: &ScriptLine,
+        raw_args: &[Vec<ArgFragment>],
+        line_number: usize,
+        filename: &str,
+    ) -> Result<Flow, ScriptError> {
+        let at_line = |e: ScriptError| e.with_location(filename, line_number);
+        if parsed.background {
+            return Err(at_line(ScriptError::syntax(format!(
+                "command {} does not support background execution (&)",
+                parsed.command
+            ))));
+        }
+        let options = wrapper.options(&joined_args(raw_args));
+        let Some(inner) = wrapped_line(parsed, raw_args, options) else {
+            return Err(at_line(ScriptError::usage(&parsed.command, &wrapper.usage().args)));
+        };
+        let options = expand_args(state, &raw_args[..options], &[]);
+
+        let mut flow = Flow::Continue;
+        let mut run_inner = |state: &mut State| -> Result<(), ScriptError> {
+            flow = self.run_command(state, &inner, inner.raw_args.clone(), line_number, filename)?;
+            Ok(())
+        };
+        // Errors of the wrapped command are already located
+        let result = wrapper
+            .run(state, &options, &mut run_inner)
+            .map_err(|e| if e.file.is_some() { e } else { at_line(e) });
+
+        match result {
+            Ok(()) if parsed.negate && matches!(flow, Flow::Continue) => Err(at_line(ScriptError::new(
+                ErrorKind::UnexpectedSuccess,
+                format!("command succeeded unexpectedly: {}", parsed.raw.trim()),
+            ))),
+            Ok(()) => Ok(flow),
+            Err(e) if e.is_skip() || e.is_stop() => Err(e),
+            // A misused wrapper or wrapped command is fatal regardless of `!` / `?`
+            Err(e) if matches!(e.kind, ErrorKind::SyntaxError | ErrorKind::UsageError) => Err(e),
+            Err(e) if (parsed.negate || parsed.may_fail) && e.kind != ErrorKind::Timeout => {
+                if !self.quiet {
+                    let outcome = if parsed.negate { "expected" } else { "allowed" };
+                    state.logf(&format!("[{} failure: {}]", outcome, e.message));
                 }
+                Ok(Flow::Continue)
             }
-
-            result.push(current_line);
-            i += 1;
+            Err(e) => Err(e),
         }
+    }
+
+    /// Check a script without running it.
+    ///
+    /// Reports syntax errors, unknown commands, unknown or misused
+    /// conditions and `&` on commands that can't run in the background.
+    pub fn check(&self, script: &str) -> Vec<Diagnostic> {
+        crate::lint::check_script(self, script, None, &[])
+    }
+
+    /// Check an archive's script without running it: everything `check`
+    /// reports, plus expected files (`cmp` golden files, `stdin` input)
+    /// that are missing from the archive.
+    pub fn check_archive(&self, archive: &emx_txtar::Archive) -> Vec<Diagnostic> {
+        self.check_archive_with(archive, &[])
+    }
 
-        result
+    /// `check_archive`, also accepting calls to `funcs` defined elsewhere
+    /// (in included archives)
+    pub(crate) fn check_archive_with(&self, archive: &emx_txtar::Archive, funcs: &[&FuncDef]) -> Vec<Diagnostic> {
+        let files: Vec<String> = archive.files.iter().map(|f| f.name.clone()).collect();
+        crate::lint::check_script(self, &archive.comment, Some(&files), funcs)
     }
 
     /// Evaluate a condition — follows Go's `conditionsActive()` closely.
     ///
     /// The condition tag may be "name" or "name:suffix".
     /// Prefix conditions (like GOOS) require a suffix; non-prefix conditions reject suffixes.
+    ///
+    /// In an expression every tag is validated before evaluation starts, so
+    /// a bad tag is reported even where `&&`/`||` would short-circuit past it.
     fn eval_condition(
         &self,
         state: &crate::state::State,
-        cond: &crate::parser::ScriptCondition,
+        cond: &ScriptCondition,
     ) -> Result<bool, ScriptError> {
+        self.check_condition(cond)?;
+        self.eval_expr(state, &cond.expr).map_err(|e| condition_context(e, cond))
+    }
+
+    fn eval_expr(&self, state: &crate::state::State, expr: &CondExpr) -> Result<bool, ScriptError> {
+        match expr {
+            CondExpr::Atom(tag) => {
+                let (condition, suffix) = self.resolve_condition(tag)?;
+                condition.eval(state, suffix.unwrap_or("")).map_err(|e| {
+                    // Go-compatible: "evaluating condition %q: <err>"
+                    ScriptError::syntax(format!(
+                        "evaluating condition {:?}: {}",
+                        tag, e.message
+                    ))
+                })
+            }
+            CondExpr::Not(inner) => Ok(!self.eval_expr(state, inner)?),
+            CondExpr::And(items) => {
+                for item in items {
+                    if !self.eval_expr(state, item)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            CondExpr::Or(items) => {
+                for item in items {
+                    if self.eval_expr(state, item)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Validate every tag of a condition against the registered conditions
+    pub(crate) fn check_condition(&self, cond: &ScriptCondition) -> Result<(), ScriptError> {
+        for tag in cond.expr.atoms() {
+            self.resolve_condition(tag).map_err(|e| condition_context(e, cond))?;
+        }
+        Ok(())
+    }
+
+    /// Look up the condition for a tag, applying the prefix rules:
+    /// "name:suffix" needs a prefix condition, plain "name" a non-prefix one.
+    pub(crate) fn resolve_condition<'a>(
+        &'a self,
+        tag: &'a str,
+    ) -> Result<(&'a crate::conditions::BoxedCondition, Option<&'a str>), ScriptError> {
         // Split tag on first ':' to separate prefix conditions
-        let (prefix, suffix, has_colon) = if let Some(colon) = cond.tag.find(':') {
-            (&cond.tag[..colon], Some(&cond.tag[colon + 1..]), true)
+        let (prefix, suffix, has_colon) = if let Some(colon) = tag.find(':') {
+            (&tag[..colon], Some(&tag[colon + 1..]), true)
         } else {
-            (cond.tag.as_str(), None, false)
+            (tag, None, false)
         };
 
         let condition = if has_colon {
@@ -474,14 +1049,7 @@ impl Engine {
             c
         };
 
-        let result = condition.eval(state, suffix.unwrap_or("")).map_err(|e| {
-            // Go-compatible: "evaluating condition %q: <err>"
-            ScriptError::syntax(format!(
-                "evaluating condition {:?}: {}",
-                cond.tag, e.message
-            ))
-        })?;
-        Ok(if cond.negate { !result } else { result })
+        Ok((condition, suffix))
     }
 }
 
@@ -491,6 +1059,41 @@ impl Default for Engine {
     }
 }
 
+/// Where the statements being run come from
+#[derive(Clone, Copy)]
+struct Source<'a> {
+    /// File named in error locations
+    filename: &'a str,
+    /// Whether this is the script being run rather than an included one
+    top_level: bool,
+}
+
+/// Name the whole expression in errors about one of its tags
+fn condition_context(err: ScriptError, cond: &ScriptCondition) -> ScriptError {
+    if cond.expr.atoms().len() > 1 {
+        ScriptError::syntax(format!("{} (in {})", err.message, cond))
+    } else {
+        err
+    }
+}
+
+/// Whether script execution continues after a statement
+enum Flow {
+    Continue,
+    Stop,
+}
+
+/// Combine parse errors into one `ScriptError` located at the first one;
+/// the others are listed in the message.
+pub(crate) fn parse_errors_to_script_error(errors: &[ParseError], filename: &str) -> ScriptError {
+    let first = &errors[0];
+    let mut message = first.message.clone();
+    for e in &errors[1..] {
+        message.push_str(&format!("\n{}:{}: {}", filename, e.line, e.message));
+    }
+    ScriptError::syntax(message).with_location(filename, first.line)
+}
+
 /// Expand arguments from raw fragments, respecting quoted/unquoted and regexp escaping.
 ///
 /// This is the Rust equivalent of Go's `expandArgs()`. For each argument:
@@ -505,6 +1108,14 @@ pub fn expand_args(
 ) -> Vec<String> {
     let mut args = Vec::with_capacity(raw_args.len());
     for (i, frags) in raw_args.iter().enumerate() {
+        // A bare `$@` is one argument per argument of the `func` call in
+        // progress (none outside a call)
+        if let [frag] = frags.as_slice() {
+            if !frag.quoted && frag.s == "$@" {
+                args.extend(state.call_args().unwrap_or_default().iter().cloned());
+                continue;
+            }
+        }
         let is_regexp = regexp_args.contains(&i);
         let mut buf = String::new();
         for frag in frags {
@@ -519,6 +1130,50 @@ pub fn expand_args(
     args
 }
 
+/// Map indices in `raw_args` to indices in `expand_args`' result, where a
+/// bare `$@` may stand for any number of arguments
+fn expanded_positions(state: &State, raw_args: &[Vec<ArgFragment>], positions: &[usize]) -> Vec<usize> {
+    let spread = state.call_args().map_or(0, |args| args.len());
+    positions
+        .iter()
+        .map(|&pos| {
+            raw_args[..pos]
+                .iter()
+                .map(|frags| match frags.as_slice() {
+                    [frag] if !frag.quoted && frag.s == "$@" => spread,
+                    _ => 1,
+                })
+                .sum::<usize>()
+        })
+        .collect()
+}
+
+/// Raw (unexpanded) arguments with each argument's fragments joined
+pub(crate) fn joined_args(raw_args: &[Vec<ArgFragment>]) -> Vec<String> {
+    raw_args
+        .iter()
+        .map(|frags| frags.iter().map(|f| f.s.as_str()).collect())
+        .collect()
+}
+
+/// The line of the command a wrapper wraps: the arguments of `line` (given
+/// as `raw_args`) after the wrapper's first `options`. `None` if no command
+/// follows them.
+pub(crate) fn wrapped_line(line: &ScriptLine, raw_args: &[Vec<ArgFragment>], options: usize) -> Option<ScriptLine> {
+    let command: String = raw_args.get(options)?.iter().map(|f| f.s.as_str()).collect();
+    let raw_args = raw_args[options + 1..].to_vec();
+    Some(ScriptLine {
+        pipes: crate::parser::pipe_positions(&command, &raw_args),
+        command,
+        raw_args,
+        conditions: Vec::new(),
+        negate: false,
+        may_fail: false,
+        background: false,
+        ..line.clone()
+    })
+}
+
 /// Returns indices of the first non-flag argument.
 /// Go-compatible: skips args starting with `-`, also handles `--` separator.
 /// Used as the `regexp_args` function for stdout, stderr, grep commands.
@@ -11,6 +11,8 @@ pub enum ErrorKind {
     UnexpectedSuccess,
     /// Pattern match failed
     PatternMismatch,
+    /// Last exit code differs from the expected one (`exitcode`)
+    ExitCodeMismatch,
     /// File comparison failed
     ComparisonFailed,
     /// File not found
@@ -29,10 +31,35 @@ pub enum ErrorKind {
     Io,
     /// One or more background commands failed
     WaitError,
+    /// A command or the whole script exceeded its deadline
+    Timeout,
     /// Other error
     Other,
 }
 
+impl ErrorKind {
+    /// Stable snake_case name, used in machine-readable reports
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::CommandFailed => "command_failed",
+            ErrorKind::UnexpectedSuccess => "unexpected_success",
+            ErrorKind::PatternMismatch => "pattern_mismatch",
+            ErrorKind::ExitCodeMismatch => "exit_code_mismatch",
+            ErrorKind::ComparisonFailed => "comparison_failed",
+            ErrorKind::FileNotFound => "file_not_found",
+            ErrorKind::FileExists => "file_exists",
+            ErrorKind::SyntaxError => "syntax_error",
+            ErrorKind::UsageError => "usage_error",
+            ErrorKind::Skip => "skip",
+            ErrorKind::Stop => "stop",
+            ErrorKind::Io => "io",
+            ErrorKind::WaitError => "wait_error",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
 /// A script error with file/line context
 #[derive(Debug)]
 pub struct ScriptError {
@@ -42,6 +69,18 @@ pub struct ScriptError {
     pub line: Option<usize>,
     pub command: Option<String>,
     pub args: Vec<String>,
+    /// How the failing line was reached when it is not in the top-level
+    /// script (e.g. through `include`), innermost first
+    pub trace: Vec<Frame>,
+}
+
+/// One step in `ScriptError::trace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// How the step was taken, e.g. "included from"
+    pub via: &'static str,
+    pub file: String,
+    pub line: usize,
 }
 
 impl ScriptError {
@@ -53,6 +92,7 @@ impl ScriptError {
             line: None,
             command: None,
             args: Vec::new(),
+            trace: Vec::new(),
         }
     }
 
@@ -62,6 +102,12 @@ impl ScriptError {
         self
     }
 
+    /// Append an outer step to the trace
+    pub fn with_frame(mut self, via: &'static str, file: impl Into<String>, line: usize) -> Self {
+        self.trace.push(Frame { via, file: file.into(), line });
+        self
+    }
+
     pub fn with_command(mut self, cmd: impl Into<String>) -> Self {
         self.command = Some(cmd.into());
         self
@@ -122,7 +168,20 @@ impl fmt::Display for ScriptError {
         } else if self.file.is_some() || self.line.is_some() {
             write!(f, " ")?;
         }
-        write!(f, "{}", self.message)
+        write!(f, "{}", self.message)?;
+        // Runs of the same frame (recursion) are shown once, with a count
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            write!(f, "\n\t{} {}:{}", frame.via, frame.file, frame.line)?;
+            let mut repeats = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                write!(f, " (and {} more times)", repeats)?;
+            }
+        }
+        Ok(())
     }
 }
 
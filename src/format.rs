@@ -0,0 +1,412 @@
+//! Canonical script formatter (`emx-testspec fmt`)
+//!
+//! Re-emits the script section of a txtar archive in one style:
+//! - conditions first, then `!`/`?`, then the command; single spaces between words
+//! - quoted text always in single quotes (`''` for a literal `'`)
+//! - inline comments as ` # text`
+//! - commands longer than `MAX_WIDTH` split with `\` continuations,
+//!   shorter ones joined onto one line
+//! - runs of blank lines collapsed; comment lines and heredoc bodies kept
+//! - commands inside `if`/`else`/`end` blocks indented by `INDENT` per level
+//!
+//! The output is re-parsed and checked against the input's fragment model
+//! (`ArgFragment`s, conditions, prefixes, heredocs) before it is returned.
+//! File sections of an archive are never touched.
+
+use crate::ast::{parse_script, BlockLine, CommandStatement, Script, Statement, StatementKind};
+use crate::parser::{ArgFragment, ParseError, ScriptLine};
+
+/// Preferred maximum line width for command lines
+const MAX_WIDTH: usize = 100;
+
+/// Indentation per block level, and of continuation lines
+const INDENT: &str = "    ";
+
+/// Format a script (the comment section of a txtar archive).
+pub fn format_script(script: &str) -> Result<String, Vec<ParseError>> {
+    let parsed = parse_script(script)?;
+    let mut formatter = Formatter {
+        source: script.lines().collect(),
+        out: String::new(),
+        pending_blank: false,
+    };
+    formatter.statements(&parsed.statements, 0);
+    let mut out = formatter.out;
+    if formatter.pending_blank {
+        out.push('\n');
+    }
+
+    verify(&parsed, &out)?;
+    Ok(out)
+}
+
+/// Format the script section of a txtar archive, leaving the file
+/// sections byte-for-byte unchanged.
+pub fn format_archive(data: &str) -> Result<String, Vec<ParseError>> {
+    let split = script_end(data);
+    let (script, files) = data.split_at(split);
+
+    let mut formatted = format_script(script)?;
+    if script.contains("\r\n") {
+        formatted = formatted.replace('\n', "\r\n");
+    }
+    formatted.push_str(files);
+    Ok(formatted)
+}
+
+/// Byte offset where the first `-- name --` file marker line starts
+pub(crate) fn script_end(data: &str) -> usize {
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        if trimmed.starts_with("-- ") && trimmed.ends_with(" --") && trimmed.len() >= 6 {
+            return offset;
+        }
+        offset += line.len();
+    }
+    data.len()
+}
+
+struct Formatter<'a> {
+    source: Vec<&'a str>,
+    out: String,
+    /// A blank line is owed before the next statement
+    pending_blank: bool,
+}
+
+impl Formatter<'_> {
+    fn statements(&mut self, statements: &[Statement], depth: usize) {
+        for stmt in statements {
+            if let StatementKind::Blank = stmt.kind {
+                // Collapse runs of blank lines; drop leading ones
+                self.pending_blank = !self.out.is_empty();
+                continue;
+            }
+            if self.pending_blank {
+                self.out.push('\n');
+                self.pending_blank = false;
+            }
+
+            match &stmt.kind {
+                StatementKind::Blank => {}
+                StatementKind::Comment { .. } => {
+                    self.out.push_str(self.source[stmt.lines.start].trim_end());
+                    self.out.push('\n');
+                }
+                StatementKind::Command(cmd) => self.command(cmd, depth),
+                StatementKind::Include(include) => {
+                    let words: Vec<String> = std::iter::once("include".to_string())
+                        .chain(include.line.raw_args.iter().map(|frags| format_arg(frags)))
+                        .collect();
+                    self.line(&words, include.line.comment.as_deref(), depth);
+                }
+                StatementKind::If(block) => {
+                    let words: Vec<String> = std::iter::once("if".to_string())
+                        .chain(block.conditions.iter().map(|c| c.to_string()))
+                        .collect();
+                    self.line(&words, block.header.comment.as_deref(), depth);
+                    self.statements(&block.then_branch, depth + 1);
+                    if let Some(else_line) = &block.else_line {
+                        self.keyword("else", else_line, depth);
+                        self.statements(&block.else_branch, depth + 1);
+                    }
+                    if let Some(end_line) = &block.end_line {
+                        self.keyword("end", end_line, depth);
+                    }
+                }
+                StatementKind::Func(func) => {
+                    let words: Vec<String> = ["func", &func.name]
+                        .into_iter()
+                        .chain(func.params.iter().map(|p| p.as_str()))
+                        .map(|w| w.to_string())
+                        .collect();
+                    self.line(&words, func.header.comment.as_deref(), depth);
+                    self.statements(&func.body, depth + 1);
+                    if let Some(end_line) = &func.end_line {
+                        self.keyword("end", end_line, depth);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Emit an `else`/`end` line; a blank line right before it is dropped
+    fn keyword(&mut self, word: &str, line: &BlockLine, depth: usize) {
+        self.pending_blank = false;
+        self.line(&[word.to_string()], line.comment.as_deref(), depth);
+    }
+
+    /// Emit a command statement with its heredoc bodies
+    fn command(&mut self, cmd: &CommandStatement, depth: usize) {
+        let line = &cmd.line;
+        let mut words: Vec<String> = line
+            .conditions
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        if line.negate {
+            words.push("!".into());
+        } else if line.may_fail {
+            words.push("?".into());
+        }
+        words.push(line.command.clone());
+        words.extend(line.raw_args.iter().map(|frags| format_arg(frags)));
+        if line.background {
+            words.push("&".into());
+        }
+        self.line(&words, line.comment.as_deref(), depth);
+
+        for heredoc in &cmd.heredocs {
+            for body_line in &self.source[heredoc.lines.clone()] {
+                self.out.push_str(body_line);
+                self.out.push('\n');
+            }
+            self.out.push_str(&heredoc.marker);
+            self.out.push('\n');
+        }
+    }
+
+    /// Emit words as one logical line at `depth`, wrapped if too long
+    fn line(&mut self, words: &[String], comment: Option<&str>, depth: usize) {
+        let indent = INDENT.repeat(depth);
+        let comment = match comment {
+            Some("") => " #".to_string(),
+            Some(text) => format!(" # {}", text),
+            None => String::new(),
+        };
+
+        self.out.push_str(&indent);
+        for (i, row) in wrap(words, indent.len(), comment.len()).iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(" \\\n");
+                self.out.push_str(&indent);
+                self.out.push_str(INDENT);
+            }
+            self.out.push_str(&row.join(" "));
+        }
+        self.out.push_str(&comment);
+        self.out.push('\n');
+    }
+}
+
+/// Greedily split words into rows of at most `MAX_WIDTH` columns, given
+/// `indent` columns before the first row and `trailer` after the last.
+/// A row never ends with a word ending in `\` or `^`, which would read as
+/// a continuation.
+fn wrap(words: &[String], indent: usize, trailer: usize) -> Vec<Vec<&str>> {
+    let one_line: usize = indent + words.iter().map(|w| w.len() + 1).sum::<usize>() + trailer;
+    if one_line <= MAX_WIDTH {
+        return vec![words.iter().map(|w| w.as_str()).collect()];
+    }
+
+    let mut rows: Vec<Vec<&str>> = vec![Vec::new()];
+    let mut width = indent;
+    for word in words {
+        let row = rows.last_mut().unwrap();
+        let ends_like_continuation = row.last().is_some_and(|w: &&str| w.ends_with(['\\', '^']));
+        // Leave room for the " \" continuation marker
+        if !row.is_empty() && width + 1 + word.len() + 2 > MAX_WIDTH && !ends_like_continuation {
+            rows.push(vec![word.as_str()]);
+            width = indent + INDENT.len() + word.len();
+        } else {
+            width += usize::from(!row.is_empty()) + word.len();
+            row.push(word.as_str());
+        }
+    }
+    rows
+}
+
+/// Emit one argument; runs of quoted fragments become one single-quoted string.
+fn format_arg(frags: &[ArgFragment]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < frags.len() {
+        if frags[i].quoted {
+            out.push('\'');
+            while i < frags.len() && frags[i].quoted {
+                out.push_str(&frags[i].s.replace('\'', "''"));
+                i += 1;
+            }
+            out.push('\'');
+        } else {
+            out.push_str(&frags[i].s);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Check that `formatted` parses to the same statements as `original`,
+/// blank lines aside.
+fn verify(original: &Script, formatted: &str) -> Result<(), Vec<ParseError>> {
+    let reparsed = parse_script(formatted)?;
+    let non_blank = |script: &Script| -> Vec<Statement> {
+        script
+            .all_statements()
+            .into_iter()
+            .filter(|s| !matches!(s.kind, StatementKind::Blank))
+            .cloned()
+            .collect()
+    };
+    let (lhs, rhs) = (non_blank(original), non_blank(&reparsed));
+
+    let mismatch = |line: usize| {
+        vec![ParseError { message: "formatting would change the meaning of this line".into(), line }]
+    };
+    if lhs.len() != rhs.len() {
+        return Err(mismatch(lhs.get(rhs.len()).map_or(1, |s| s.line_number())));
+    }
+    for (a, b) in lhs.iter().zip(&rhs) {
+        let same = match (&a.kind, &b.kind) {
+            (
+                StatementKind::Comment { text: t1, section: s1 },
+                StatementKind::Comment { text: t2, section: s2 },
+            ) => t1 == t2 && s1 == s2,
+            (StatementKind::Command(c1), StatementKind::Command(c2)) => same_command(c1, c2),
+            (StatementKind::Include(i1), StatementKind::Include(i2)) => {
+                i1.path == i2.path && i1.line.comment == i2.line.comment
+            }
+            (StatementKind::If(b1), StatementKind::If(b2)) => {
+                let count = |branch: &[Statement]| {
+                    branch.iter().filter(|s| !matches!(s.kind, StatementKind::Blank)).count()
+                };
+                let shape = |b: &crate::ast::IfBlock| {
+                    (
+                        b.conditions.iter().map(|c| c.expr.clone()).collect::<Vec<_>>(),
+                        b.header.comment.clone(),
+                        count(&b.then_branch),
+                        b.else_line.as_ref().map(|l| l.comment.clone()),
+                        count(&b.else_branch),
+                        b.end_line.as_ref().map(|l| l.comment.clone()),
+                    )
+                };
+                shape(b1) == shape(b2)
+            }
+            (StatementKind::Func(f1), StatementKind::Func(f2)) => {
+                let shape = |f: &crate::ast::FuncDef| {
+                    (
+                        f.name.clone(),
+                        f.params.clone(),
+                        f.header.comment.clone(),
+                        f.body.iter().filter(|s| !matches!(s.kind, StatementKind::Blank)).count(),
+                        f.end_line.as_ref().map(|l| l.comment.clone()),
+                    )
+                };
+                shape(f1) == shape(f2)
+            }
+            _ => false,
+        };
+        if !same {
+            return Err(mismatch(a.line_number()));
+        }
+    }
+    Ok(())
+}
+
+fn same_command(a: &CommandStatement, b: &CommandStatement) -> bool {
+    let key = |l: &ScriptLine| {
+        (
+            l.negate,
+            l.may_fail,
+            l.conditions.iter().map(|c| c.expr.clone()).collect::<Vec<_>>(),
+            l.command.clone(),
+            l.raw_args.iter().map(|frags| canonical(frags)).collect::<Vec<_>>(),
+            l.background,
+            l.comment.clone(),
+        )
+    };
+    let heredocs = |c: &CommandStatement| {
+        c.heredocs.iter().map(|h| (h.marker.clone(), h.body.clone())).collect::<Vec<_>>()
+    };
+    key(&a.line) == key(&b.line) && heredocs(a) == heredocs(b)
+}
+
+/// Fragments with adjacent runs of the same quotedness merged
+fn canonical(frags: &[ArgFragment]) -> Vec<ArgFragment> {
+    let mut out: Vec<ArgFragment> = Vec::new();
+    for frag in frags {
+        match out.last_mut() {
+            Some(last) if last.quoted == frag.quoted => last.s.push_str(&frag.s),
+            _ => out.push(frag.clone()),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(s: &str) -> String {
+        let once = format_script(s).unwrap();
+        assert_eq!(format_script(&once).unwrap(), once, "not idempotent");
+        once
+    }
+
+    #[test]
+    fn test_format_quoting_and_spacing() {
+        assert_eq!(
+            fmt("exec   echo \"hello world\"  'it''s' a\"b\"c   \n"),
+            "exec echo 'hello world' 'it''s' a'b'c\n"
+        );
+        assert_eq!(fmt("stdout \"say 'hi'\"\n"), "stdout 'say ''hi'''\n");
+    }
+
+    #[test]
+    fn test_format_prefixes_conditions_and_comments() {
+        assert_eq!(
+            fmt("!  [!windows]   [unix] exec  false   #   expect failure  \n"),
+            "[!windows] [unix] ! exec false # expect failure\n"
+        );
+        assert_eq!(fmt("exec sleep 1   &\n"), "exec sleep 1 &\n");
+    }
+
+    #[test]
+    fn test_format_continuations() {
+        assert_eq!(fmt("exec echo a ^\n   b \\\n c\n"), "exec echo a b c\n");
+
+        let long_args: Vec<String> = (0..30).map(|i| format!("argument{}", i)).collect();
+        let long = format!("exec mytool {}\n", long_args.join(" "));
+        let formatted = fmt(&long);
+        assert!(formatted.lines().count() > 1);
+        assert!(formatted.lines().all(|l| l.len() <= MAX_WIDTH));
+        assert!(formatted.contains(" \\\n    "));
+    }
+
+    #[test]
+    fn test_format_blank_lines_comments_heredocs() {
+        let script = "\n\n# section  \n\n\n  # indented\nexec echo hi\ncmp stdout <<EOF\n  body  kept  \n   EOF\n\n\n";
+        assert_eq!(
+            fmt(script),
+            "# section\n\n  # indented\nexec echo hi\ncmp stdout <<EOF\n  body  kept  \nEOF\n\n"
+        );
+
+        // Heredoc bodies are kept line for line, blank and continued lines too
+        let script = "cmp stdout <<EOF\n\na \\\nb\n\n\nEOF\n";
+        assert_eq!(fmt(script), script);
+    }
+
+    #[test]
+    fn test_format_if_blocks() {
+        let script = "if [unix]  # posix\nexec echo a\nif [!windows]\n  exec echo b\nend\n\nelse\n# note\n   exec echo c\nend\n";
+        assert_eq!(
+            fmt(script),
+            "if [unix] # posix\n    exec echo a\n    if [!windows]\n        exec echo b\n    end\nelse\n# note\n    exec echo c\nend\n"
+        );
+    }
+
+    #[test]
+    fn test_format_archive_keeps_files() {
+        let data = "exec  echo hi\r\ncmp stdout  want.txt\r\n\r\n-- want.txt --\r\nhi  \r\n-- other --\nraw   text\n";
+        assert_eq!(
+            format_archive(data).unwrap(),
+            "exec echo hi\r\ncmp stdout want.txt\r\n\r\n-- want.txt --\r\nhi  \r\n-- other --\nraw   text\n"
+        );
+    }
+
+    #[test]
+    fn test_format_rejects_syntax_errors() {
+        let errors = format_script("exec 'open\n").unwrap_err();
+        assert_eq!(errors[0].line, 1);
+    }
+}
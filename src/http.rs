@@ -0,0 +1,248 @@
+//! Canned-response HTTP server for `httpserve`
+//!
+//! Routes are read from an archive file, one per line:
+//!
+//! ```text
+//! # method path status [body-file]
+//! GET /api/users 200 users.json
+//!     Content-Type: application/json
+//! POST /api/login 401
+//! * /health 204
+//! ```
+//!
+//! Indented lines are response headers of the route above. The method `*`
+//! matches any method; a path without `?` matches any query string. The
+//! first matching route answers; other requests get a 404.
+//!
+//! Every request is appended to a log as its request line followed by its
+//! body. The server answers one request per connection, in order.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request body read into the log
+const MAX_BODY: usize = 16 << 20;
+
+/// One canned response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Route {
+    fn matches(&self, method: &str, target: &str) -> bool {
+        let path = if self.path.contains('?') { target } else { target.split('?').next().unwrap_or_default() };
+        (self.method == "*" || self.method == method) && self.path == path
+    }
+}
+
+/// Parse a routes file; `body` loads a named body file.
+pub fn parse_routes(
+    text: &str,
+    mut body: impl FnMut(&str) -> Result<String, String>,
+) -> Result<Vec<Route>, String> {
+    let mut routes: Vec<Route> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let at_line = |msg: String| format!("line {}: {}", i + 1, msg);
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            let route = routes.last_mut().ok_or_else(|| at_line("header before the first route".into()))?;
+            let (name, value) = trimmed
+                .split_once(':')
+                .filter(|(name, _)| !name.is_empty() && !name.contains(' '))
+                .ok_or_else(|| at_line(format!("invalid header {:?}, want Name: value", trimmed)))?;
+            route.headers.push((name.to_string(), value.trim().to_string()));
+            continue;
+        }
+
+        let words: Vec<&str> = trimmed.split_whitespace().collect();
+        let [method, path, status, rest @ ..] = words.as_slice() else {
+            return Err(at_line("want method path status [body-file]".into()));
+        };
+        if rest.len() > 1 {
+            return Err(at_line("want method path status [body-file]".into()));
+        }
+        if !path.starts_with('/') {
+            return Err(at_line(format!("path {:?} must start with /", path)));
+        }
+        let status: u16 = status
+            .parse()
+            .ok()
+            .filter(|s| (100..=999).contains(s))
+            .ok_or_else(|| at_line(format!("invalid status {:?}", status)))?;
+        let body = match rest.first() {
+            Some(file) => body(file).map_err(|e| at_line(format!("{}: {}", file, e)))?,
+            None => String::new(),
+        };
+        routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            status,
+            headers: Vec::new(),
+            body,
+        });
+    }
+    Ok(routes)
+}
+
+/// A running server
+pub struct Server {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<Result<Vec<u8>, String>>,
+}
+
+impl Server {
+    /// Serve `routes` on `listener`, appending requests to the file `log`
+    pub fn start(listener: TcpListener, routes: Vec<Route>, log: &Path) -> std::io::Result<Self> {
+        let addr = listener.local_addr()?;
+        let mut log_file = std::fs::File::create(log)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                // A client that goes away mid-request doesn't stop the server
+                let Ok(stream) = stream else { continue };
+                let Ok(entry) = serve(stream, &routes) else { continue };
+                log_file
+                    .write_all(&entry)
+                    .and_then(|_| log_file.flush())
+                    .map_err(|e| format!("writing request log: {}", e))?;
+                requests.extend(entry);
+            }
+            Ok(requests)
+        });
+        Ok(Self { addr, stop, thread })
+    }
+
+    /// Ask the server to stop after the request in progress
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the accept loop
+        let _ = TcpStream::connect_timeout(&self.addr, Duration::from_secs(1));
+    }
+
+    /// Stop the server and return its request log
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        self.stop();
+        self.thread.join().unwrap_or_else(|_| Err("server thread panicked".into()))
+    }
+}
+
+/// Answer one request; returns its log entry
+fn serve(stream: TcpStream, routes: &[Route]) -> std::io::Result<Vec<u8>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed request line"));
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = Vec::new();
+    reader.take(content_length.min(MAX_BODY) as u64).read_to_end(&mut body)?;
+
+    let (status, headers, content) = match routes.iter().find(|r| r.matches(method, target)) {
+        Some(route) => (route.status, route.headers.as_slice(), route.body.clone()),
+        None => (404, &[][..], format!("no route for {} {}\n", method, target)),
+    };
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", content.len()));
+    response.push_str(&content);
+    (&stream).write_all(response.as_bytes())?;
+
+    let mut entry = format!("{} {}\n", method, target).into_bytes();
+    if !body.is_empty() {
+        entry.extend(&body);
+        if !body.ends_with(b"\n") {
+            entry.push(b'\n');
+        }
+    }
+    Ok(entry)
+}
+
+/// Reason phrase for a status code
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Status",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_routes() {
+        let text = "# routes\nGET /users 200 users.json\n    Content-Type: application/json\n\n* /health?full=1 204\n";
+        let routes = parse_routes(text, |name| Ok(format!("<{}>", name))).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].body, "<users.json>");
+        assert_eq!(routes[0].headers, vec![("Content-Type".to_string(), "application/json".to_string())]);
+        assert!(routes[0].matches("GET", "/users?page=2"));
+        assert!(!routes[0].matches("POST", "/users"));
+        assert!(routes[1].matches("DELETE", "/health?full=1"));
+        assert!(!routes[1].matches("GET", "/health"));
+
+        let err = |text: &str| parse_routes(text, |_| Err("missing".into())).unwrap_err();
+        assert_eq!(err("  X-A: 1\n"), "line 1: header before the first route");
+        assert_eq!(err("GET /a\n"), "line 1: want method path status [body-file]");
+        assert_eq!(err("GET a 200\n"), "line 1: path \"a\" must start with /");
+        assert_eq!(err("\nGET /a OK\n"), "line 2: invalid status \"OK\"");
+        assert_eq!(err("GET /a 200 body.txt\n"), "line 1: body.txt: missing");
+        assert!(err("GET /a 200\n  no colon\n").starts_with("line 2: invalid header"));
+    }
+}
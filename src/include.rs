@@ -0,0 +1,185 @@
+//! `include` resolution
+//!
+//! `include path.txtar` runs another archive's script at that point. The
+//! engine loads the archive when the `include` line runs, so includes in
+//! branches not taken or funcs never called are never read. The included
+//! archive's files are extracted then, except those already in the
+//! workdir, so the including archive wins on conflicts. Paths are relative
+//! to the including file; includes nest, and cycles are an error.
+//!
+//! [`load_script`] instead loads every include up front, wherever it is;
+//! `lint` uses it to see all files a script may use.
+//!
+//! Errors inside an included file are located in that file, with an
+//! "included from" frame for each `include` line that led there.
+
+use std::path::{Path, PathBuf};
+
+use crate::ast::{parse_script, Include, Statement, StatementKind, Script};
+use crate::engine::parse_errors_to_script_error;
+use crate::error::{ErrorKind, ScriptError};
+
+/// A script with every `include` loaded
+#[derive(Debug, Clone)]
+pub struct LoadedScript {
+    /// The parsed script; `Include` statements carry the included statements
+    pub script: Script,
+    /// Files of the included archives, in extraction order
+    pub included_files: Vec<emx_txtar::File>,
+}
+
+/// Parse `script` (the comment section of the archive at `file`) and load
+/// its includes recursively.
+pub fn load_script(file: &Path, script: &str) -> Result<LoadedScript, ScriptError> {
+    let filename = file.to_string_lossy().to_string();
+    let mut script = parse_script(script).map_err(|errors| parse_errors_to_script_error(&errors, &filename))?;
+
+    let mut stack = vec![canonical(file)];
+    let mut included_files = Vec::new();
+    resolve(&mut script.statements, file, &mut stack, &mut included_files)?;
+    Ok(LoadedScript { script, included_files })
+}
+
+/// Load the includes among `statements` (of the script in `file`)
+fn resolve(
+    statements: &mut [Statement],
+    file: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<emx_txtar::File>,
+) -> Result<(), ScriptError> {
+    let filename = file.to_string_lossy().to_string();
+    for stmt in statements {
+        let line_number = stmt.line_number();
+        let include = match &mut stmt.kind {
+            StatementKind::Include(include) => include,
+            StatementKind::If(block) => {
+                resolve(&mut block.then_branch, file, stack, files)?;
+                resolve(&mut block.else_branch, file, stack, files)?;
+                continue;
+            }
+            StatementKind::Func(func) => {
+                resolve(&mut func.body, file, stack, files)?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        let mut included = read_include(include, &filename, line_number, stack)?;
+
+        let from_here = |e: ScriptError| e.with_frame("included from", &filename, line_number);
+        stack.push(canonical(&included.file));
+        resolve(&mut included.statements, &included.file, stack, files).map_err(from_here)?;
+        stack.pop();
+
+        // Nested includes' files first, then this archive's own
+        files.extend(included.files);
+        include.file = Some(included.file);
+        include.statements = included.statements;
+    }
+    Ok(())
+}
+
+/// An included archive, with its own includes not loaded yet
+pub(crate) struct IncludedArchive {
+    /// The archive's path
+    pub file: PathBuf,
+    /// Its script
+    pub statements: Vec<Statement>,
+    /// Its files
+    pub files: Vec<emx_txtar::File>,
+}
+
+/// Read and parse the archive named by `include`, found at `line_number`
+/// of `filename`. `stack` holds the canonical paths of the archives being
+/// run, outermost first; including one of them again is a cycle.
+pub(crate) fn read_include(
+    include: &Include,
+    filename: &str,
+    line_number: usize,
+    stack: &[PathBuf],
+) -> Result<IncludedArchive, ScriptError> {
+    let target = Path::new(filename).parent().unwrap_or(Path::new("")).join(&include.path);
+    let at_include = |e: ScriptError| e.with_location(filename, line_number);
+    let key = canonical(&target);
+    if stack.contains(&key) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&key))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(at_include(ScriptError::syntax(format!("include cycle: {}", chain.join(" -> ")))));
+    }
+
+    let data = std::fs::read_to_string(&target).map_err(|e| {
+        at_include(ScriptError::new(ErrorKind::FileNotFound, format!("include {}: {}", include.path, e)))
+    })?;
+    let archive = emx_txtar::Decoder::new().decode(&data).map_err(|e| {
+        at_include(ScriptError::syntax(format!("include {}: failed to parse txtar: {}", include.path, e)))
+    })?;
+
+    let target_name = target.to_string_lossy().to_string();
+    let included = parse_script(&archive.comment).map_err(|errors| {
+        parse_errors_to_script_error(&errors, &target_name).with_frame("included from", filename, line_number)
+    })?;
+    Ok(IncludedArchive { file: target, statements: included.statements, files: archive.files })
+}
+
+/// Canonical path for cycle detection; falls back to the path as given
+pub(crate) fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_nested_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "lib/base.txtar", "echo base\n-- base.txt --\nbase\n-- shared.txt --\nfrom base\n");
+        write(dir.path(), "lib/common.txtar", "include base.txtar\necho common\n-- shared.txt --\nfrom common\n");
+        let main = write(dir.path(), "main.txtar", "");
+
+        let loaded = load_script(&main, "include lib/common.txtar\necho main\n").unwrap();
+        let names: Vec<&str> = loaded.included_files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["base.txt", "shared.txt", "shared.txt"]);
+
+        let StatementKind::Include(include) = &loaded.script.statements[0].kind else { panic!("expected include") };
+        assert!(include.file.as_ref().unwrap().ends_with("lib/common.txtar"));
+        assert_eq!(include.statements.len(), 2);
+        assert_eq!(loaded.script.all_statements().len(), 5);
+    }
+
+    #[test]
+    fn test_load_include_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txtar", "include b.txtar\n");
+        write(dir.path(), "b.txtar", "echo ok\ninclude a.txtar\n");
+        write(dir.path(), "bad.txtar", "echo ok\necho 'open\n");
+        let main = write(dir.path(), "main.txtar", "");
+
+        let err = load_script(&main, "\ninclude a.txtar\n").unwrap_err();
+        assert!(err.message.starts_with("include cycle: "), "{}", err.message);
+        assert!(err.file.as_deref().unwrap().ends_with("b.txtar"));
+        assert_eq!(err.line, Some(2));
+        let via: Vec<usize> = err.trace.iter().map(|f| f.line).collect();
+        assert_eq!(via, vec![1, 2]);
+
+        let err = load_script(&main, "include bad.txtar\n").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::SyntaxError);
+        assert!(err.file.as_deref().unwrap().ends_with("bad.txtar"));
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.trace[0].line, 1);
+
+        let err = load_script(&main, "echo\ninclude missing.txtar\n").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::FileNotFound);
+        assert_eq!((err.line, err.trace.len()), (Some(2), 0));
+    }
+}
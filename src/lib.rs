@@ -48,22 +48,40 @@
 //! - `!` - Command must fail
 //! - `?` - Command may succeed or fail
 //! - `[cond]` - Conditional execution
+//! - `if [cond]` ... `else` ... `end` - Conditional blocks
+//! - `func name [param...]` ... `end` - Macros called like commands, with `$1`/`$@`
+//! - `include path.txtar` - Run another archive's script and extract its files
 
 mod engine;
+mod ast;
+mod lint;
+mod include;
+mod format;
+mod http;
+mod lsp;
 mod state;
 mod parser;
+mod pty;
 mod commands;
 mod conditions;
 mod runner;
 mod error;
+mod report;
+mod signal;
 
-pub use engine::{Engine, Cmd, CmdUsage, CmdResult, expand_args, first_non_flag};
-pub use state::State;
-pub use parser::{ScriptLine, ArgFragment, parse_line};
-pub use commands::default_commands;
+pub use engine::{Engine, Cmd, CmdContext, CmdUsage, CmdResult, WrapCmd, expand_args, first_non_flag};
+pub use state::{State, GoldenUpdate, ScriptFunc};
+pub use parser::{ScriptLine, ScriptCondition, CondExpr, ArgFragment, ParseError, parse_line};
+pub use lint::Diagnostic;
+pub use format::{format_script, format_archive};
+pub use lsp::LanguageServer;
+pub use ast::{parse_script, parse_script_partial, Script, Statement, StatementKind, CommandStatement, Heredoc, IfBlock, FuncDef, BlockLine, Include};
+pub use include::{load_script, LoadedScript};
+pub use commands::{default_commands, default_wrappers, parse_go_duration};
 pub use conditions::{Condition, default_conditions};
 pub use runner::{TestRunner, RunConfig, TestResult, TestCaseResult, TestRunnerBuilder, SetupEnv};
-pub use error::{ScriptError, ErrorKind};
+pub use error::{ScriptError, ErrorKind, Frame};
+pub use report::{junit_xml, Reporter, JsonReporter};
 
 // Convenience functions for cargo test integration
 pub use runner::{run_and_assert, run_and_assert_with, run};
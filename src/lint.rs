@@ -0,0 +1,400 @@
+//! Static script checks — `Engine::check` / `Engine::check_archive`
+//!
+//! Everything here works on the parsed script alone; no command is run and
+//! no condition is evaluated.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{parse_script_partial, FuncDef, StatementKind};
+use crate::engine::{joined_args, wrapped_line, Engine};
+use crate::parser::{ArgFragment, ScriptLine};
+
+/// A problem found in a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 1-based script line
+    pub line: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Check `script`. With `files` (the archive's file names), also report
+/// expected files that the archive doesn't contain. Calls to the script's
+/// own `func` macros and to `funcs` are checked against their parameters.
+pub(crate) fn check_script(
+    engine: &Engine,
+    script: &str,
+    files: Option<&[String]>,
+    funcs: &[&FuncDef],
+) -> Vec<Diagnostic> {
+    let (parsed, errors) = parse_script_partial(script);
+    let mut diags: Vec<Diagnostic> = errors
+        .into_iter()
+        .map(|e| Diagnostic { line: e.line, message: e.message })
+        .collect();
+
+    let statements = parsed.all_statements();
+    let mut known_funcs: HashMap<&str, &FuncDef> = funcs.iter().map(|f| (f.name.as_str(), *f)).collect();
+    for stmt in &statements {
+        if let StatementKind::Func(func) = &stmt.kind {
+            known_funcs.insert(&func.name, func);
+        }
+    }
+
+    let mut files = files.map(FileTracker::new);
+
+    for stmt in statements {
+        let mut report = |message: String| diags.push(Diagnostic { line: stmt.line_number(), message });
+        let line = match &stmt.kind {
+            StatementKind::Command(cmd) => &cmd.line,
+            StatementKind::If(block) => {
+                for cond in &block.conditions {
+                    if let Err(e) = engine.check_condition(cond) {
+                        report(e.message);
+                    }
+                }
+                continue;
+            }
+            StatementKind::Func(func) => {
+                if engine.has_command(&func.name) {
+                    report(format!("func {0}: {0} is already a command", func.name));
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        for cond in &line.conditions {
+            if let Err(e) = engine.check_condition(cond) {
+                report(e.message);
+            }
+        }
+
+        // A wrapper is checked as the command it wraps
+        let wrapped;
+        let line = match engine.wrappers.get(&line.command) {
+            Some(wrapper) => {
+                if line.background {
+                    report(format!("command {} does not support background execution (&)", line.command));
+                }
+                let options = wrapper.options(&joined_args(&line.raw_args));
+                match wrapped_line(line, &line.raw_args, options) {
+                    Some(inner) => {
+                        wrapped = inner;
+                        &wrapped
+                    }
+                    None => {
+                        report(format!("usage: {} {}", line.command, wrapper.usage().args));
+                        continue;
+                    }
+                }
+            }
+            None => line,
+        };
+
+        let Some(command) = engine.commands.get(&line.command) else {
+            match known_funcs.get(line.command.as_str()) {
+                Some(func) => {
+                    if line.background {
+                        report(format!("command {} does not support background execution (&)", line.command));
+                    }
+                    // A bare `$@` passes on any number of arguments
+                    let spread = line.raw_args.iter().any(|frags| {
+                        matches!(frags.as_slice(), [frag] if !frag.quoted && frag.s == "$@")
+                    });
+                    if !spread && !func.accepts(line.raw_args.len()) {
+                        report(func.usage());
+                    }
+                    if let Some(files) = files.as_mut() {
+                        files.forget();
+                    }
+                }
+                None => report(format!("unknown command: {}", line.command)),
+            }
+            continue;
+        };
+        let usage = command.usage();
+        if line.background && !usage.async_ {
+            report(format!("command {} does not support background execution (&)", line.command));
+        }
+
+        if let Some(files) = files.as_mut() {
+            for name in files.missing_inputs(line) {
+                report(format!("{}: no such file in archive", name));
+            }
+            files.track(line, usage.creates_files);
+        }
+    }
+
+    diags.sort_by_key(|d| d.line);
+    diags
+}
+
+/// Tracks which relative paths exist in the workdir as the script goes:
+/// archive files plus `cp`/`mv` destinations. Only operands that normally
+/// come from the archive are checked: the expected (second) file of
+/// `cmp`/`cmpenv`/`cmpjson` and `stdin` input. Checking stops after any
+/// command that may create other files (`exec`, funcs, background jobs, ...).
+struct FileTracker {
+    known: HashSet<String>,
+    /// Current directory relative to the workdir; `None` once unknown or
+    /// once the workdir may hold files that aren't tracked
+    cwd: Option<String>,
+}
+
+impl FileTracker {
+    fn new(names: &[String]) -> Self {
+        Self {
+            known: names.iter().map(|n| normalize("", n)).collect(),
+            cwd: Some(String::new()),
+        }
+    }
+
+    /// Expected-input operands of `line` that don't exist
+    fn missing_inputs(&self, line: &ScriptLine) -> Vec<String> {
+        let Some(cwd) = &self.cwd else { return Vec::new() };
+        let args: Vec<Option<String>> = line.raw_args.iter().map(|a| literal(a)).collect();
+
+        let inputs: Vec<&Option<String>> = match line.command.as_str() {
+            "cmp" | "cmpenv" => {
+                let operands: Vec<_> =
+                    args.iter().filter(|a| !matches!(a.as_deref(), Some("-q" | "-lf"))).collect();
+                operands.get(1).copied().into_iter().collect()
+            }
+            "cmpjson" => {
+                // Ignore paths start with `$`, so look at the raw text
+                let operands: Vec<_> = args
+                    .iter()
+                    .zip(&line.raw_args)
+                    .filter(|(_, raw)| !raw.first().is_some_and(|f| f.s.starts_with("-ignore=")))
+                    .map(|(a, _)| a)
+                    .collect();
+                operands.get(1).copied().into_iter().collect()
+            }
+            "stdin" => args.first().into_iter().collect(),
+            "exec" => args
+                .iter()
+                .take_while(|a| a.as_deref().is_some_and(|a| a.starts_with('-')))
+                .filter(|a| a.as_deref().is_some_and(|a| a.starts_with("-stdin=")))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        inputs
+            .into_iter()
+            .flatten()
+            .map(|name| name.strip_prefix("-stdin=").unwrap_or(name))
+            .filter(|name| !matches!(*name, "stdout" | "stderr") && !is_absolute(name))
+            .filter(|name| !self.known.contains(&normalize(cwd, name)))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// Stop checking: the workdir may now hold files we don't know about
+    fn forget(&mut self) {
+        self.cwd = None;
+    }
+
+    /// Record files and directory changes made by `line`, whose command
+    /// may create other files if `creates_files`
+    fn track(&mut self, line: &ScriptLine, creates_files: bool) {
+        let Some(cwd) = self.cwd.clone() else { return };
+        if line.background {
+            self.forget();
+            return;
+        }
+        let last = line.raw_args.last().map(|a| literal(a));
+        match line.command.as_str() {
+            "cp" | "mv" => match last {
+                Some(Some(dst)) => {
+                    self.known.insert(normalize(&cwd, &dst));
+                    // The destination may be a directory: accept the source names in it too
+                    for src in line.raw_args[..line.raw_args.len() - 1].iter().filter_map(|a| literal(a)) {
+                        let base = src.rsplit('/').next().unwrap_or(&src).to_string();
+                        self.known.insert(normalize(&cwd, &format!("{}/{}", dst, base)));
+                    }
+                }
+                _ => self.forget(),
+            },
+            "cd" => {
+                self.cwd = match last {
+                    Some(Some(dir)) if !is_absolute(&dir) => Some(normalize(&cwd, &dir)),
+                    _ => None,
+                };
+            }
+            _ if !creates_files => {}
+            _ => self.forget(),
+        }
+    }
+}
+
+/// The literal value of an argument, or `None` if it depends on the
+/// environment or a heredoc.
+fn literal(frags: &[ArgFragment]) -> Option<String> {
+    let dynamic = frags.iter().any(|f| !f.quoted && (f.s.contains('$') || f.s.starts_with("<<")));
+    if dynamic {
+        return None;
+    }
+    Some(frags.iter().map(|f| f.s.as_str()).collect())
+}
+
+fn is_absolute(path: &str) -> bool {
+    std::path::Path::new(path).is_absolute() || path.starts_with('/')
+}
+
+/// Join `path` onto `cwd` and normalize `.`/`..` and separators
+fn normalize(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = cwd.split('/').filter(|p| !p.is_empty()).collect();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(diags: &[Diagnostic]) -> Vec<usize> {
+        diags.iter().map(|d| d.line).collect()
+    }
+
+    #[test]
+    fn test_check_commands_and_conditions() {
+        let engine = Engine::new();
+        let diags = engine.check("exec echo ok\nfrobnicate\n[nosuchcond] echo x\n[exec] echo x\n[unix:foo] echo x\necho x &\n");
+        assert_eq!(lines(&diags), vec![2, 3, 4, 5, 6]);
+        assert_eq!(diags[0].message, "unknown command: frobnicate");
+        assert!(diags[2].message.contains("requires a suffix"));
+        assert!(diags[3].message.contains("cannot be used with a suffix"));
+        assert!(diags[4].message.contains("background"));
+    }
+
+    #[test]
+    fn test_check_condition_expressions() {
+        let engine = Engine::new();
+        let diags = engine.check("[unix || exec:sh] echo ok\n[unix || bogus] echo x\nif [windows && GOOS]\nend\n");
+        assert_eq!(lines(&diags), vec![2, 3]);
+        assert!(diags[0].message.contains("\"bogus\"") && diags[0].message.ends_with("(in [unix || bogus])"));
+        assert!(diags[1].message.contains("\"GOOS\"") && diags[1].message.ends_with("(in [windows && GOOS])"));
+    }
+
+    #[test]
+    fn test_check_eventually() {
+        let engine = Engine::new();
+        let diags = engine.check(
+            "eventually -timeout=5s stdout ready\neventually frobnicate\neventually -interval=1s\neventually exec srv &\neventually exec srv\n",
+        );
+        assert_eq!(lines(&diags), vec![2, 3, 4]);
+        assert_eq!(diags[0].message, "unknown command: frobnicate");
+        assert!(diags[1].message.starts_with("usage: eventually "));
+        assert!(diags[2].message.contains("background"));
+    }
+
+    #[test]
+    fn test_check_funcs() {
+        let engine = Engine::new();
+        let script = "\
+func greet name
+    echo hello $1
+end
+func echo
+end
+greet world
+greet
+greet a b
+greet $@
+greet x &
+nosuch
+";
+        let diags = engine.check(script);
+        assert_eq!(lines(&diags), vec![4, 7, 8, 10, 11]);
+        assert_eq!(diags[0].message, "func echo: echo is already a command");
+        assert_eq!(diags[1].message, "usage: greet name");
+        assert!(diags[3].message.contains("background"));
+        assert_eq!(diags[4].message, "unknown command: nosuch");
+    }
+
+    #[test]
+    fn test_check_reports_syntax_errors_with_other_problems() {
+        let engine = Engine::new();
+        let diags = engine.check("echo 'open\nbogus\ncmp stdout <<EOF\nx\n");
+        assert_eq!(lines(&diags), vec![1, 2, 3]);
+        assert!(diags[2].message.contains("unterminated heredoc"));
+    }
+
+    #[test]
+    fn test_check_archive_files() {
+        let engine = Engine::new();
+        let files = vec!["want.txt".to_string(), "sub/in.txt".to_string()];
+        let script = "\
+cmp stdout want.txt
+cmp stdout missing.txt
+cmp -q stdout $WORK/dynamic.txt
+stdin sub/in.txt
+cp stdout made.txt
+cmp stdout ./made.txt
+cd sub
+cmp stdout in.txt
+cmpjson -ignore=$.id stdout in.txt
+cmpjson -ignore=$.id stdout want.json
+exec -stdin=nope.txt cat
+";
+        let diags = check_script(&engine, script, Some(&files), &[]);
+        assert_eq!(lines(&diags), vec![2, 10, 11]);
+        assert_eq!(diags[0].message, "missing.txt: no such file in archive");
+    }
+
+    #[test]
+    fn test_check_archive_files_stops_after_writes() {
+        let engine = Engine::new();
+        let files = vec!["want.txt".to_string()];
+        for setup in [
+            "exec sh -c 'echo x > made.txt'",
+            "exec sleep 1 &",
+            "cp want.txt $WORK/out",
+            "mkdir out",
+            "setup",
+        ] {
+            let script = format!("func setup\necho set up\nend\n{}\ncmp stdout made.txt\n", setup);
+            let diags = check_script(&engine, &script, Some(&files), &[]);
+            assert!(diags.is_empty(), "{}: {:?}", setup, diags);
+        }
+    }
+
+    #[test]
+    fn test_check_archive_files_after_custom_command() {
+        use crate::engine::{Cmd, CmdResult, CmdUsage};
+        use crate::error::ScriptError;
+        use crate::state::State;
+
+        // A replacement for a built-in that does create files
+        struct Touch;
+        impl Cmd for Touch {
+            fn run(&self, _state: &mut State, _args: &[String]) -> Result<CmdResult, ScriptError> {
+                Ok(CmdResult::Ok)
+            }
+
+            fn usage(&self) -> CmdUsage {
+                CmdUsage { summary: "touch".into(), args: "file".into(), regexp_args: None, async_: false, creates_files: true }
+            }
+        }
+
+        let mut engine = Engine::new();
+        engine.register_command("echo", Box::new(Touch));
+        let diags = check_script(&engine, "echo made.txt\ncmp stdout made.txt\n", Some(&[]), &[]);
+        assert!(diags.is_empty(), "{:?}", diags);
+    }
+}
@@ -0,0 +1,513 @@
+//! Language server for `.txtar` testspec files (`emx-testspec-lsp`)
+//!
+//! Speaks LSP over JSON-RPC with `Content-Length` framing. `serve` works on
+//! any reader/writer pair, so the server runs the same over stdio or in a
+//! test. Everything comes from the engine's registries and static checks:
+//!
+//! - completion: command names (`Engine::commands` and the script's `func`
+//!   macros) at the start of a command line, condition names
+//!   (`Engine::conditions`) inside `[...]`
+//! - hover: `CmdUsage` summary and args for commands, the parameters of
+//!   macros, the summary for conditions
+//! - diagnostics: parser errors and lint checks (`Engine::check_archive`),
+//!   published when a document is opened or changed
+//! - go-to-definition: from a file name argument to its `-- name --` line,
+//!   from a macro call to its `func` line
+//!
+//! Positions follow LSP: 0-based lines, UTF-16 character offsets.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::ast::{parse_script_partial, FuncDef, Statement, StatementKind};
+use crate::engine::Engine;
+use crate::lint::Diagnostic;
+
+/// LSP `CompletionItemKind` values
+const KIND_FUNCTION: u32 = 3;
+const KIND_CONSTANT: u32 = 21;
+
+/// A language server over one `Engine`
+pub struct LanguageServer {
+    engine: Engine,
+    /// Open documents by URI
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl LanguageServer {
+    /// Create a server using `engine`'s commands and conditions
+    pub fn new(engine: Engine) -> Self {
+        Self { engine, documents: HashMap::new(), shutdown: false }
+    }
+
+    /// Read framed messages from `input` until `exit` or end of input,
+    /// writing responses and notifications to `output`.
+    pub fn serve<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        while let Some(body) = read_message(&mut input)? {
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(m) => m,
+                Err(e) => {
+                    write_message(&mut output, &error_response(Value::Null, -32700, &e.to_string()))?;
+                    continue;
+                }
+            };
+            if message["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&message) {
+                write_message(&mut output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a `shutdown` request has been received
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    /// Handle one decoded message, returning the messages to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+
+        let Some(id) = message.get("id").cloned() else {
+            // Notifications
+            return match method {
+                "textDocument/didOpen" => {
+                    let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                    self.documents.insert(uri.clone(), text);
+                    vec![self.publish_diagnostics(&uri)]
+                }
+                "textDocument/didChange" => {
+                    // Full sync: the last change holds the whole text
+                    if let Some(text) = params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str())
+                    {
+                        self.documents.insert(uri.clone(), text.to_string());
+                    }
+                    vec![self.publish_diagnostics(&uri)]
+                }
+                "textDocument/didClose" => {
+                    self.documents.remove(&uri);
+                    vec![notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))]
+                }
+                _ => Vec::new(),
+            };
+        };
+        if method.is_empty() {
+            // A response to something we never send
+            return Vec::new();
+        }
+
+        let position = (
+            params["position"]["line"].as_u64().unwrap_or(0) as usize,
+            params["position"]["character"].as_u64().unwrap_or(0) as usize,
+        );
+        let text = self.documents.get(&uri).map(|t| t.as_str()).unwrap_or("");
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": ["["] },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "emx-testspec-lsp", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/completion" => Value::Array(self.completion(text, position)),
+            "textDocument/hover" => self.hover(text, position).unwrap_or(Value::Null),
+            "textDocument/definition" => definition(&uri, text, position).unwrap_or(Value::Null),
+            _ => return vec![error_response(id, -32601, &format!("method not found: {}", method))],
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map(|t| t.as_str()).unwrap_or("");
+        let diags = match emx_txtar::Decoder::new().decode(text) {
+            Ok(archive) => self.engine.check_archive(&archive),
+            Err(e) => vec![Diagnostic { line: 1, message: format!("failed to parse txtar: {}", e) }],
+        };
+        let diagnostics: Vec<Value> = diags
+            .iter()
+            .map(|d| {
+                let line = d.line.saturating_sub(1);
+                let width = text.lines().nth(line).map_or(0, utf16_len);
+                json!({
+                    "range": range(line, 0, line, width),
+                    "severity": 1,
+                    "source": "emx-testspec",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diagnostics }))
+    }
+
+    fn completion(&self, text: &str, (line, character): (usize, usize)) -> Vec<Value> {
+        let Some(src) = command_line(text, line) else { return Vec::new() };
+        let before = &src[..byte_offset(src, character)];
+
+        // Inside an unclosed `[`: conditions
+        if let Some(open) = before.rfind('[') {
+            if !before[open..].contains(']') {
+                let mut names: Vec<_> = self.engine.conditions.iter().collect();
+                names.sort_by_key(|(name, _)| name.as_str());
+                return names
+                    .into_iter()
+                    .map(|(name, cond)| {
+                        let insert = if cond.is_prefix() { format!("{}:", name) } else { name.clone() };
+                        json!({
+                            "label": name,
+                            "kind": KIND_CONSTANT,
+                            "detail": cond.summary(),
+                            "insertText": insert,
+                        })
+                    })
+                    .collect();
+            }
+        }
+
+        // Command position: only conditions and prefixes before the cursor's word
+        let mut words: Vec<&str> = before.split_whitespace().collect();
+        if !before.is_empty() && !before.ends_with(char::is_whitespace) {
+            words.pop();
+        }
+        let is_prefix = |w: &&str| *w == "!" || *w == "?" || (w.starts_with('[') && w.ends_with(']'));
+        if !words.iter().all(is_prefix) {
+            return Vec::new();
+        }
+
+        let commands = self.engine.usages().into_iter().map(|(name, usage)| {
+            let detail = format!("{} {}", name, usage.args);
+            json!({
+                "label": name,
+                "kind": KIND_FUNCTION,
+                "detail": detail.trim_end(),
+                "documentation": usage.summary,
+            })
+        });
+        let funcs = script_funcs(text).into_iter().map(|(at, func)| {
+            json!({
+                "label": func.name,
+                "kind": KIND_FUNCTION,
+                "detail": signature(&func),
+                "documentation": format!("func defined on line {}", at + 1),
+            })
+        });
+        commands.chain(funcs).collect()
+    }
+
+    fn hover(&self, text: &str, (line, character): (usize, usize)) -> Option<Value> {
+        let src = command_line(text, line)?;
+        let (start, end) = word_at(src, byte_offset(src, character))?;
+        let word = &src[start..end];
+
+        let in_brackets = src[..start].matches('[').count() > src[..start].matches(']').count();
+        let contents = if word.starts_with('[') || in_brackets {
+            let tag = word.trim_matches(|c| "[]()!".contains(c));
+            let (cond, _) = self.engine.resolve_condition(tag).ok()?;
+            format!("`[{}]`\n\n{}", tag, cond.summary())
+        } else {
+            let stmt = statement_at(text, line)?;
+            let StatementKind::Command(cmd) = &stmt.kind else { return None };
+            if cmd.line.command != word {
+                return None;
+            }
+            match self.engine.usage(word) {
+                Some(usage) => {
+                    format!("```text\n{} {}\n```\n\n{}", word, usage.args, usage.summary)
+                }
+                None => {
+                    let (at, func) = script_funcs(text).into_iter().find(|(_, f)| f.name == word)?;
+                    format!("```text\n{}\n```\n\nfunc defined on line {}", signature(&func), at + 1)
+                }
+            }
+        };
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(line, utf16_len(&src[..start]), line, utf16_len(&src[..end])),
+        }))
+    }
+}
+
+/// Location of the `-- name --` line for the file name under the cursor
+fn definition(uri: &str, text: &str, (line, character): (usize, usize)) -> Option<Value> {
+    let src = command_line(text, line)?;
+    let (start, end) = word_at(src, byte_offset(src, character))?;
+    // Accept `-flag=file` operands and quoted names
+    let word = &src[start..end];
+
+    // A macro call
+    if let Some(StatementKind::Command(cmd)) = statement_at(text, line).map(|s| s.kind) {
+        if cmd.line.command == word {
+            let (at, _) = script_funcs(text).into_iter().find(|(_, f)| f.name == word)?;
+            return Some(json!({
+                "uri": uri,
+                "range": range(at, 0, at, text.lines().nth(at).map_or(0, utf16_len)),
+            }));
+        }
+    }
+
+    let word = word.rsplit_once('=').map_or(word, |(_, value)| value);
+    let name = word.trim_matches(|c| c == '\'' || c == '"');
+    let name = name.strip_prefix("./").unwrap_or(name);
+
+    let target = text.lines().position(|l| marker_name(l) == Some(name))?;
+    Some(json!({
+        "uri": uri,
+        "range": range(target, 0, target, text.lines().nth(target).map_or(0, utf16_len)),
+    }))
+}
+
+/// The source of script line `line` if it holds (part of) a command, and
+/// not a heredoc body, comment or file section
+fn command_line(text: &str, line: usize) -> Option<&str> {
+    let stmt = statement_at(text, line)?;
+    match &stmt.kind {
+        StatementKind::Command(cmd) if cmd.heredocs.iter().any(|h| h.lines.contains(&line) || h.lines.end == line) => None,
+        StatementKind::Comment { .. } => None,
+        _ => text.lines().nth(line),
+    }
+}
+
+/// The statement covering `line`; lines with syntax errors count as blank
+fn statement_at(text: &str, line: usize) -> Option<Statement> {
+    let script = &text[..crate::format::script_end(text)];
+    if line >= script.lines().count() {
+        return None;
+    }
+    let (parsed, _) = parse_script_partial(script);
+    // The innermost statement: blocks come before their contents
+    let found = parsed.all_statements().into_iter().rev().find(|s| s.lines.contains(&line)).cloned();
+    Some(found.unwrap_or(Statement { lines: line..line + 1, kind: StatementKind::Blank }))
+}
+
+/// The `func` macros defined in the script part of `text`, with the
+/// (0-based) line of each definition
+fn script_funcs(text: &str) -> Vec<(usize, FuncDef)> {
+    let (parsed, _) = parse_script_partial(&text[..crate::format::script_end(text)]);
+    parsed
+        .statements
+        .into_iter()
+        .filter_map(|stmt| match stmt.kind {
+            StatementKind::Func(func) => Some((stmt.lines.start, func)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `name params...` of a macro
+fn signature(func: &FuncDef) -> String {
+    func.usage().trim_start_matches("usage: ").to_string()
+}
+
+/// The file name in a txtar marker line
+fn marker_name(line: &str) -> Option<&str> {
+    let line = line.trim_end_matches('\r');
+    if line.len() >= 6 && line.starts_with("-- ") && line.ends_with(" --") {
+        Some(line[3..line.len() - 3].trim())
+    } else {
+        None
+    }
+}
+
+/// Byte range of the whitespace-delimited word around byte offset `at`
+fn word_at(line: &str, at: usize) -> Option<(usize, usize)> {
+    let start = line[..at].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let end = line[at..].find(char::is_whitespace).map_or(line.len(), |i| at + i);
+    (start < end).then_some((start, end))
+}
+
+/// Byte offset of UTF-16 offset `character` in `line`, clamped to its end
+fn byte_offset(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+fn range(start_line: usize, start_char: usize, end_line: usize, end_char: usize) -> Value {
+    json!({
+        "start": { "line": start_line, "character": start_char },
+        "end": { "line": end_line, "character": end_char },
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Read one `Content-Length` framed message body; `None` at end of input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///t/basic.txtar";
+    const DOC: &str = "[unix] exec echo hi\ncmp stdout want.txt\nfrobnicate\n\n-- want.txt --\nhi\n";
+
+    fn server() -> LanguageServer {
+        let mut server = LanguageServer::new(Engine::new());
+        server.handle(&json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": DOC } },
+        }));
+        server
+    }
+
+    fn request(server: &mut LanguageServer, method: &str, line: usize, character: usize) -> Value {
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0", "id": 1, "method": method,
+            "params": { "textDocument": { "uri": URI }, "position": { "line": line, "character": character } },
+        }));
+        replies[0]["result"].clone()
+    }
+
+    fn labels(items: &Value) -> Vec<String> {
+        items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_lsp_completion() {
+        let mut server = server();
+        let commands = labels(&request(&mut server, "textDocument/completion", 0, 7));
+        assert!(commands.contains(&"exec".to_string()) && commands.contains(&"cmp".to_string()));
+
+        let conditions = labels(&request(&mut server, "textDocument/completion", 0, 2));
+        assert!(conditions.contains(&"unix".to_string()));
+        assert!(!conditions.contains(&"cmp".to_string()));
+
+        // Argument position and file sections complete nothing
+        assert_eq!(request(&mut server, "textDocument/completion", 1, 10), json!([]));
+        assert_eq!(request(&mut server, "textDocument/completion", 5, 1), json!([]));
+    }
+
+    #[test]
+    fn test_lsp_hover() {
+        let mut server = server();
+        let hover = request(&mut server, "textDocument/hover", 1, 1);
+        let value = hover["contents"]["value"].as_str().unwrap();
+        assert!(value.contains("cmp [-q] [-lf] file1 file2") && value.contains("Compare two files"));
+        assert_eq!(hover["range"], range(1, 0, 1, 3));
+
+        let hover = request(&mut server, "textDocument/hover", 0, 3);
+        assert!(hover["contents"]["value"].as_str().unwrap().starts_with("`[unix]`"));
+
+        assert_eq!(request(&mut server, "textDocument/hover", 1, 5), Value::Null);
+    }
+
+    #[test]
+    fn test_lsp_funcs() {
+        let mut server = LanguageServer::new(Engine::new());
+        server.handle(&json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": "func greet name\n    echo hi $1\nend\ngreet world\n" } },
+        }));
+
+        let completions = labels(&request(&mut server, "textDocument/completion", 3, 0));
+        assert!(completions.contains(&"greet".to_string()));
+        let hover = request(&mut server, "textDocument/hover", 3, 1);
+        assert!(hover["contents"]["value"].as_str().unwrap().contains("greet name"));
+        let location = request(&mut server, "textDocument/definition", 3, 1);
+        assert_eq!(location["range"]["start"]["line"], 0);
+    }
+
+    #[test]
+    fn test_lsp_definition() {
+        let mut server = server();
+        let location = request(&mut server, "textDocument/definition", 1, 14);
+        assert_eq!(location["uri"], URI);
+        assert_eq!(location["range"]["start"]["line"], 4);
+        assert_eq!(request(&mut server, "textDocument/definition", 1, 8), Value::Null);
+    }
+
+    #[test]
+    fn test_lsp_serve_diagnostics_and_shutdown() {
+        let messages = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                    "params": { "textDocument": { "uri": URI, "text": DOC } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+
+        let mut server = LanguageServer::new(Engine::new());
+        let mut output = Vec::new();
+        server.serve(&input[..], &mut output).unwrap();
+        assert!(server.is_shutdown());
+
+        let mut reader = &output[..];
+        let mut replies = Vec::new();
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            replies.push(serde_json::from_slice::<Value>(&body).unwrap());
+        }
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let diagnostics = replies[1]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "unknown command: frobnicate");
+        assert_eq!(diagnostics[0]["range"], range(2, 0, 2, 10));
+        assert_eq!(replies[2]["result"], Value::Null);
+    }
+}
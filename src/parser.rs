@@ -5,7 +5,8 @@
 //! - `#` mid-line terminates the argument list (inline comment)
 //! - `!` prefix for expected-failure
 //! - `?` prefix for may-fail
-//! - `[cond]` for conditional execution
+//! - `[cond]` for conditional execution; `cond` may be an expression of
+//!   tags with `!`, `&&`, `||` and parentheses, and may contain spaces
 //! - Single-quote strings disable word splitting and env expansion
 //! - Double-quote strings disable word splitting and env expansion (Windows-style)
 //! - `''` inside single quotes produces a literal `'`
@@ -53,15 +54,92 @@ pub struct ScriptLine {
     pub line_number: usize,
     /// Whether the command should run in the background (& suffix)
     pub background: bool,
+    /// Trailing inline comment: the text after an unquoted `#`
+    pub comment: Option<String>,
+    /// For `exec`, the indices in `raw_args` of the `|` arguments that
+    /// start a new pipeline stage (see `pipe_positions`)
+    pub pipes: Vec<usize>,
 }
 
 /// A condition guard on a script line
 #[derive(Debug, Clone)]
 pub struct ScriptCondition {
-    /// The full condition tag (e.g. "unix", "GOOS:linux", "exec:git")
+    /// The full condition tag (e.g. "unix", "GOOS:linux", "exec:git").
+    /// For a compound expression, the expression text.
     pub tag: String,
-    /// Whether the condition is negated
+    /// Whether the condition is a negated single tag
     pub negate: bool,
+    /// The parsed condition expression
+    pub expr: CondExpr,
+}
+
+impl std::fmt::Display for ScriptCondition {
+    /// Canonical `[...]` form
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.expr)
+    }
+}
+
+/// A condition expression: tags combined with `!`, `&&`, `||` and parentheses.
+/// `&&` binds tighter than `||`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CondExpr {
+    /// A condition tag such as `unix`, `GOOS:linux` or `exec:git`
+    Atom(String),
+    /// `!expr`
+    Not(Box<CondExpr>),
+    /// `a && b && ...` (at least two operands, none of them `And`)
+    And(Vec<CondExpr>),
+    /// `a || b || ...` (at least two operands, none of them `Or`)
+    Or(Vec<CondExpr>),
+}
+
+impl CondExpr {
+    /// The tags in the expression, left to right
+    pub fn atoms(&self) -> Vec<&str> {
+        match self {
+            CondExpr::Atom(tag) => vec![tag.as_str()],
+            CondExpr::Not(inner) => inner.atoms(),
+            CondExpr::And(items) | CondExpr::Or(items) => items.iter().flat_map(|e| e.atoms()).collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for CondExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CondExpr::Atom(tag) => write!(f, "{}", tag),
+            CondExpr::Not(inner) => match **inner {
+                CondExpr::And(_) | CondExpr::Or(_) => write!(f, "!({})", inner),
+                _ => write!(f, "!{}", inner),
+            },
+            CondExpr::And(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" && ")?;
+                    }
+                    match item {
+                        CondExpr::Or(_) => write!(f, "({})", item)?,
+                        _ => write!(f, "{}", item)?,
+                    }
+                }
+                Ok(())
+            }
+            CondExpr::Or(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" || ")?;
+                    }
+                    // Parenthesized for readability; `&&` binds tighter anyway
+                    match item {
+                        CondExpr::And(_) => write!(f, "({})", item)?,
+                        _ => write!(f, "{}", item)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 /// Characters that separate arguments (same as Go's argSepChars)
@@ -80,6 +158,147 @@ impl std::fmt::Display for ParseError {
     }
 }
 
+/// Parse a `[cond]` word (which may contain spaces) into a condition.
+pub(crate) fn parse_condition(word: &str, line_number: usize) -> Result<ScriptCondition, ParseError> {
+    let error = |message: String| ParseError { message, line: line_number };
+    let inner = word
+        .strip_prefix('[')
+        .and_then(|w| w.strip_suffix(']'))
+        .ok_or_else(|| error(format!("expected [condition], found {:?}", word)))?
+        .trim();
+    if inner.is_empty() {
+        return Err(error("empty condition".into()));
+    }
+
+    let tokens = cond_tokens(inner);
+    let mut pos = 0;
+    let expr = parse_cond_or(&tokens, &mut pos).map_err(|e| error(format!("{} in condition [{}]", e, inner)))?;
+    if let Some(token) = tokens.get(pos) {
+        return Err(error(format!("unexpected {:?} in condition [{}]", token, inner)));
+    }
+
+    let (tag, negate) = match &expr {
+        CondExpr::Atom(tag) => (tag.clone(), false),
+        CondExpr::Not(atom) => match &**atom {
+            CondExpr::Atom(tag) => (tag.clone(), true),
+            _ => (inner.to_string(), false),
+        },
+        _ => (inner.to_string(), false),
+    };
+    Ok(ScriptCondition { tag, negate, expr })
+}
+
+/// Split a condition expression into tags and the operators
+/// `!`, `&&`, `||`, `(`, `)`
+fn cond_tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let len = if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else if rest.starts_with(['!', '(', ')']) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || "!()&|".contains(c))
+                .map_or(rest.len(), |n| n.max(1))
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+fn parse_cond_or(tokens: &[&str], pos: &mut usize) -> Result<CondExpr, String> {
+    let mut items = vec![parse_cond_and(tokens, pos)?];
+    while tokens.get(*pos) == Some(&"||") {
+        *pos += 1;
+        items.push(parse_cond_and(tokens, pos)?);
+    }
+    Ok(combine(items, true))
+}
+
+fn parse_cond_and(tokens: &[&str], pos: &mut usize) -> Result<CondExpr, String> {
+    let mut items = vec![parse_cond_unary(tokens, pos)?];
+    while tokens.get(*pos) == Some(&"&&") {
+        *pos += 1;
+        items.push(parse_cond_unary(tokens, pos)?);
+    }
+    Ok(combine(items, false))
+}
+
+fn parse_cond_unary(tokens: &[&str], pos: &mut usize) -> Result<CondExpr, String> {
+    let Some(&token) = tokens.get(*pos) else {
+        return Err("missing condition".into());
+    };
+    *pos += 1;
+    match token {
+        "!" => Ok(CondExpr::Not(Box::new(parse_cond_unary(tokens, pos)?))),
+        "(" => {
+            let inner = parse_cond_or(tokens, pos)?;
+            if tokens.get(*pos) != Some(&")") {
+                return Err("missing )".into());
+            }
+            *pos += 1;
+            Ok(inner)
+        }
+        ")" | "&&" | "||" | "&" | "|" => Err(format!("unexpected {:?}", token)),
+        tag => Ok(CondExpr::Atom(tag.to_string())),
+    }
+}
+
+/// Build an `And` (or, with `or`, an `Or`) node from its operands,
+/// flattening nested nodes of the same kind so every expression has one
+/// canonical shape.
+fn combine(items: Vec<CondExpr>, or: bool) -> CondExpr {
+    if items.len() == 1 {
+        return items.into_iter().next().unwrap();
+    }
+    let mut flat = Vec::new();
+    for item in items {
+        match item {
+            CondExpr::Or(inner) if or => flat.extend(inner),
+            CondExpr::And(inner) if !or => flat.extend(inner),
+            item => flat.push(item),
+        }
+    }
+    if or {
+        CondExpr::Or(flat)
+    } else {
+        CondExpr::And(flat)
+    }
+}
+
+/// Parse a sequence of whitespace-separated `[cond]` groups, such as the
+/// arguments of an `if` line.
+pub(crate) fn parse_conditions(words: &[String], line_number: usize) -> Result<Vec<ScriptCondition>, ParseError> {
+    let mut conditions = Vec::new();
+    let mut open: Option<String> = None;
+    for word in words {
+        let text = match open.take() {
+            Some(prev) => format!("{} {}", prev, word),
+            None if word.starts_with('[') => word.clone(),
+            None => {
+                return Err(ParseError {
+                    message: format!("expected [condition], found {:?}", word),
+                    line: line_number,
+                })
+            }
+        };
+        if text.ends_with(']') {
+            conditions.push(parse_condition(&text, line_number)?);
+        } else {
+            open = Some(text);
+        }
+    }
+    if open.is_some() {
+        return Err(ParseError {
+            message: "unterminated condition: missing ]".into(),
+            line: line_number,
+        });
+    }
+    Ok(conditions)
+}
+
 /// Parse a single script line into a ScriptLine.
 ///
 /// Returns `Ok(None)` for blank lines and comment-only lines.
@@ -102,13 +321,27 @@ pub fn parse_line(line: &str, line_number: usize) -> Result<Option<ScriptLine>,
     let mut negate = false;
     let mut may_fail = false;
     let mut conditions: Vec<ScriptCondition> = Vec::new();
+    // A `[condition` whose closing `]` is in a later word
+    let mut open_condition: Option<String> = None;
     let mut command: Option<String> = None;
+    let mut comment: Option<String> = None;
 
     /// Flush the current word (fragments) into either a prefix/condition/command/arg
     macro_rules! flush_arg {
         () => {
             if current_frags.is_empty() {
                 // Nothing to flush
+            } else if let Some(mut text) = open_condition.take() {
+                // Continue a condition containing spaces
+                for frag in current_frags.drain(..) {
+                    text.push_str(&frag.s);
+                }
+                if text.ends_with(']') {
+                    conditions.push(parse_condition(&text, line_number)?);
+                } else {
+                    text.push(' ');
+                    open_condition = Some(text);
+                }
             } else {
                 // If no command name yet, first word is a potential prefix or command
                 if command.is_none() && current_frags.len() == 1 && !current_frags[0].quoted {
@@ -135,22 +368,10 @@ pub fn parse_line(line: &str, line_number: usize) -> Result<Option<ScriptLine>,
                         current_frags.clear();
                     } else if arg.starts_with('[') && arg.ends_with(']') {
                         // Condition guard
-                        let inner = arg[1..arg.len()-1].trim();
-                        let (want_true, tag) = if let Some(rest) = inner.strip_prefix('!') {
-                            (false, rest.trim())
-                        } else {
-                            (true, inner)
-                        };
-                        if tag.is_empty() {
-                            return Err(ParseError {
-                                message: "empty condition".into(),
-                                line: line_number,
-                            });
-                        }
-                        conditions.push(ScriptCondition {
-                            tag: tag.to_string(),
-                            negate: !want_true,
-                        });
+                        conditions.push(parse_condition(arg, line_number)?);
+                        current_frags.clear();
+                    } else if arg.starts_with('[') {
+                        open_condition = Some(format!("{} ", arg));
                         current_frags.clear();
                     } else if arg.is_empty() {
                         return Err(ParseError {
@@ -185,7 +406,11 @@ pub fn parse_line(line: &str, line_number: usize) -> Result<Option<ScriptLine>,
                 start = None;
             }
             flush_arg!();
-            if i >= len || line_bytes[i] == b'#' {
+            if i >= len {
+                break;
+            }
+            if line_bytes[i] == b'#' {
+                comment = Some(line[i + 1..].trim().to_string());
                 break;
             }
             i += 1;
@@ -247,6 +472,13 @@ pub fn parse_line(line: &str, line_number: usize) -> Result<Option<ScriptLine>,
         i += 1;
     }
 
+    if open_condition.is_some() {
+        return Err(ParseError {
+            message: "unterminated condition: missing ]".into(),
+            line: line_number,
+        });
+    }
+
     if command.is_none() {
         if negate || may_fail || !conditions.is_empty() || !raw_args.is_empty() {
             return Err(ParseError {
@@ -268,21 +500,47 @@ pub fn parse_line(line: &str, line_number: usize) -> Result<Option<ScriptLine>,
         }
     }
 
+    let command = command.unwrap();
+    let pipes = pipe_positions(&command, &raw_args);
     Ok(Some(ScriptLine {
         negate,
         may_fail,
         conditions,
-        command: command.unwrap(),
+        command,
         raw_args,
         raw: line.to_string(),
         line_number,
         background,
+        comment,
+        pipes,
     }))
 }
 
+/// Indices of the arguments of an `exec` line that separate pipeline
+/// stages: an unquoted `|` followed by an unquoted `exec`. A quoted `'|'`,
+/// or a variable holding `|`, is a plain argument. Empty for other commands.
+pub(crate) fn pipe_positions(command: &str, raw_args: &[Vec<ArgFragment>]) -> Vec<usize> {
+    if command != "exec" {
+        return Vec::new();
+    }
+    let bare = |frags: &[ArgFragment], word: &str| matches!(frags, [frag] if !frag.quoted && frag.s == word);
+    let mut pipes = Vec::new();
+    let mut i = 0;
+    while i + 1 < raw_args.len() {
+        if bare(raw_args[i].as_slice(), "|") && bare(raw_args[i + 1].as_slice(), "exec") {
+            pipes.push(i);
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    pipes
+}
+
 /// Expand environment variables in a string.
 /// Supports `$VAR` and `${VAR}` syntax.
 /// Special variables: `${/}` → path separator, `${:}` → path list separator.
+/// `$?` is shorthand for `${?}`, the last exit code.
 ///
 /// When `in_regexp` is true, expanded values are escaped with `regex::escape()`
 /// (equivalent to Go's `regexp.QuoteMeta`).
@@ -329,14 +587,21 @@ pub fn expand_env(s: &str, lookup: &dyn Fn(&str) -> Option<String>, in_regexp: b
                 }
             }
         } else {
-            // $VAR syntax - read until non-alphanumeric/underscore
+            // $VAR syntax - read until non-alphanumeric/underscore.
+            // Go-compatible (os.Expand): special parameters are one character,
+            // so `$12` is `$1` followed by `2`.
             let mut var_name = String::new();
-            while let Some(&c) = chars.peek() {
-                if c.is_alphanumeric() || c == '_' {
-                    var_name.push(c);
-                    chars.next();
-                } else {
-                    break;
+            if let Some(&c) = chars.peek().filter(|c| matches!(c, '?' | '@' | '0'..='9')) {
+                var_name.push(c);
+                chars.next();
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        var_name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
                 }
             }
 
@@ -428,6 +693,46 @@ mod tests {
         assert!(!line.conditions[0].negate);
     }
 
+    #[test]
+    fn test_parse_condition_expression() {
+        let line = parse_line("[linux || darwin] [exec:git && !windows] exec git", 1).unwrap().unwrap();
+        assert_eq!(line.command, "exec");
+        assert_eq!(line.conditions.len(), 2);
+        assert_eq!(
+            line.conditions[0].expr,
+            CondExpr::Or(vec![CondExpr::Atom("linux".into()), CondExpr::Atom("darwin".into())])
+        );
+        assert_eq!(line.conditions[1].tag, "exec:git && !windows");
+        assert!(!line.conditions[1].negate);
+        assert_eq!(line.conditions[1].expr.atoms(), vec!["exec:git", "windows"]);
+    }
+
+    #[test]
+    fn test_parse_condition_grouping_and_display() {
+        let line = parse_line("[!(a||b) && (c || d&&e) && (f && g)] cmd", 1).unwrap().unwrap();
+        let cond = &line.conditions[0];
+        assert_eq!(cond.to_string(), "[!(a || b) && (c || (d && e)) && f && g]");
+        // The canonical form parses back to the same expression
+        let again = parse_line(&format!("{} cmd", cond), 1).unwrap().unwrap();
+        assert_eq!(again.conditions[0].expr, cond.expr);
+
+        let line = parse_line("[ !windows ] cmd", 1).unwrap().unwrap();
+        assert_eq!((line.conditions[0].tag.as_str(), line.conditions[0].negate), ("windows", true));
+    }
+
+    #[test]
+    fn test_parse_condition_expression_errors() {
+        for (input, message) in [
+            ("[a ||] cmd", "missing condition in condition [a ||]"),
+            ("[(a && b] cmd", "missing ) in condition [(a && b]"),
+            ("[a b] cmd", "unexpected \"b\" in condition [a b]"),
+            ("[a & b] cmd", "unexpected \"&\" in condition [a & b]"),
+            ("[a || b cmd", "unterminated condition: missing ]"),
+        ] {
+            assert_eq!(parse_line(input, 1).unwrap_err().message, message, "{}", input);
+        }
+    }
+
     #[test]
     fn test_parse_quoted_args() {
         let line = parse_line("stdout 'hello world'", 1).unwrap().unwrap();
@@ -469,6 +774,7 @@ mod tests {
         let line = parse_line("echo hello # this is a comment", 1).unwrap().unwrap();
         assert_eq!(line.command, "echo");
         assert_eq!(flat_args(&line), vec!["hello"]);
+        assert_eq!(line.comment.as_deref(), Some("this is a comment"));
     }
 
     #[test]
@@ -532,6 +838,22 @@ mod tests {
         assert_eq!(result, "hello  end");
     }
 
+    #[test]
+    fn test_expand_env_exit_code() {
+        let lookup = |key: &str| if key == "?" { Some("3".to_string()) } else { None };
+        assert_eq!(expand_env("code=$? ${?}", &lookup, false), "code=3 3");
+    }
+
+    #[test]
+    fn test_expand_env_positional() {
+        let lookup = |key: &str| match key {
+            "1" => Some("a".to_string()),
+            "@" => Some("a b".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_env("$1 $12 ${1}x [$@]", &lookup, false), "a a2 ax [a b]");
+    }
+
     #[test]
     fn test_expand_env_in_regexp() {
         // When in_regexp=true, values should be regex-escaped
@@ -549,6 +871,20 @@ mod tests {
         assert_eq!(flat_args(&line), vec!["sleep", "1"]);
     }
 
+    #[test]
+    fn test_parse_pipes() {
+        let line = parse_line("exec echo a | exec cat | exec wc -l", 1).unwrap().unwrap();
+        assert_eq!(line.pipes, vec![2, 5]);
+
+        // A quoted or expanded `|` is a plain argument
+        let line = parse_line("exec echo '|' exec", 1).unwrap().unwrap();
+        assert!(line.pipes.is_empty());
+        let line = parse_line("exec tool $SEP exec", 1).unwrap().unwrap();
+        assert!(line.pipes.is_empty());
+        let line = parse_line("echo a | exec b", 1).unwrap().unwrap();
+        assert!(line.pipes.is_empty());
+    }
+
     #[test]
     fn test_parse_quoted_ampersand_not_background() {
         // Go-compatible: a quoted '&' should NOT be treated as background
@@ -0,0 +1,300 @@
+//! Pseudo-terminal sessions (`exec -pty`, `expect`, `send`)
+//!
+//! `exec -pty program &` runs the program in a new session with a pty as
+//! its controlling terminal and stdin/stdout/stderr. A reader thread copies
+//! everything the program writes (including the terminal's echo of what
+//! `send` typed) into a shared transcript, which `expect` waits on and
+//! which becomes the job's stdout when it is waited for.
+//!
+//! Only supported on Linux.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::{Child, Command};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How long to keep reading after the program exits, for output still in
+/// the terminal. A grandchild may hold the pty open; we don't wait for it.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Window size reported to the program
+#[cfg(target_os = "linux")]
+const ROWS: u16 = 24;
+#[cfg(target_os = "linux")]
+const COLS: u16 = 80;
+
+/// Everything the program under a pty has written so far
+#[derive(Default)]
+pub struct Transcript {
+    inner: Mutex<TranscriptInner>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TranscriptInner {
+    data: Vec<u8>,
+    /// The terminal was closed (every copy of the pty slave, i.e. the
+    /// program and anything it started, is gone)
+    closed: bool,
+}
+
+/// Why `Transcript::wait_for` gave up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmatched {
+    /// The deadline passed
+    TimedOut,
+    /// The terminal was closed
+    Closed,
+}
+
+impl Transcript {
+    /// Start copying `master` into a new transcript on a background thread
+    fn spawn_reader(mut master: File) -> Arc<Self> {
+        let transcript = Arc::new(Self::default());
+        let sink = Arc::clone(&transcript);
+        std::thread::spawn(move || {
+            let mut chunk = [0u8; 4096];
+            loop {
+                // Linux reports EIO once the slave side is closed
+                let n = match master.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                sink.lock().data.extend_from_slice(&chunk[..n]);
+                sink.changed.notify_all();
+            }
+            sink.lock().closed = true;
+            sink.changed.notify_all();
+        });
+        transcript
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TranscriptInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A copy of the transcript so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.lock().data.clone()
+    }
+
+    /// The transcript once the program has exited, as script output (see
+    /// `to_output`): waits (briefly) for the terminal to close so its last
+    /// output isn't lost
+    pub fn finish(&self) -> Vec<u8> {
+        let until = Instant::now() + DRAIN_TIMEOUT;
+        let mut inner = self.lock();
+        while !inner.closed {
+            let now = Instant::now();
+            if now >= until {
+                break;
+            }
+            inner = self.changed.wait_timeout(inner, until - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        to_output(&inner.data)
+    }
+
+    /// Wait until `find` succeeds on the transcript from byte `from` on,
+    /// re-running it as output arrives.
+    pub fn wait_for<T>(
+        &self,
+        from: usize,
+        deadline: Instant,
+        mut find: impl FnMut(&[u8]) -> Option<T>,
+    ) -> Result<T, Unmatched> {
+        let mut inner = self.lock();
+        loop {
+            if let Some(found) = find(inner.data.get(from..).unwrap_or_default()) {
+                return Ok(found);
+            }
+            if inner.closed {
+                return Err(Unmatched::Closed);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Unmatched::TimedOut);
+            }
+            inner = self.changed.wait_timeout(inner, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+}
+
+/// The pty of the last `exec -pty`, as driven by `expect` and `send`
+pub struct PtySession {
+    /// The master side; writes are the program's keyboard input
+    master: File,
+    transcript: Arc<Transcript>,
+    /// Transcript offset up to which `expect` has consumed output
+    pub matched: usize,
+}
+
+impl PtySession {
+    pub fn transcript(&self) -> &Arc<Transcript> {
+        &self.transcript
+    }
+
+    /// Type `bytes` into the terminal
+    pub fn send(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let mut master = &self.master;
+        master.write_all(bytes)?;
+        master.flush()
+    }
+}
+
+/// Spawn `cmd` on a new pty, replacing its stdin, stdout and stderr.
+#[cfg(target_os = "linux")]
+pub fn spawn(mut cmd: Command) -> std::io::Result<(Child, PtySession)> {
+    use std::ffi::CStr;
+    use std::io::Error;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: plain syscall; the new fd is owned by `master` from here on
+    let master = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+
+    let fd = master.as_raw_fd();
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: `fd` is an open pty master and `name` is writable for its length
+    let slave_path = unsafe {
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(Error::last_os_error());
+        }
+        let err = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+        if err != 0 {
+            return Err(Error::from_raw_os_error(err));
+        }
+        CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned()
+    };
+    let slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(slave_path)?;
+
+    let size = libc::winsize { ws_row: ROWS, ws_col: COLS, ws_xpixel: 0, ws_ypixel: 0 };
+    // SAFETY: `slave` is an open terminal and `size` outlives the call
+    if unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    cmd.stdin(slave.try_clone()?);
+    cmd.stdout(slave.try_clone()?);
+    cmd.stderr(slave);
+    // SAFETY: only async-signal-safe syscalls run between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            // A new session, with the pty (now stdin) as controlling terminal
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn()?;
+    // Close our copies of the slave, so the reader sees the terminal close
+    // once the program (and whatever it started) is gone
+    drop(cmd);
+
+    let transcript = Transcript::spawn_reader(master.try_clone()?);
+    Ok((child, PtySession { master, transcript, matched: 0 }))
+}
+
+/// Spawn `cmd` on a new pty, replacing its stdin, stdout and stderr.
+#[cfg(not(target_os = "linux"))]
+pub fn spawn(_cmd: Command) -> std::io::Result<(Child, PtySession)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "pseudo-terminals are only supported on Linux",
+    ))
+}
+
+/// Transcript bytes as script output: the terminal's `\r\n` line endings
+/// become `\n`, so `stdout` patterns and `cmp` work as for plain `exec`
+pub fn to_output(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if !(b == b'\r' && data.get(i + 1) == Some(&b'\n')) {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// Decode the escapes `send` accepts: `\n`, `\r`, `\t`, `\e` (escape),
+/// `\\` and `\xHH`.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('\\') {
+        out.extend_from_slice(&rest.as_bytes()[..pos]);
+        let escape = &rest[pos + 1..];
+        let (byte, len) = match escape.as_bytes().first() {
+            Some(b'n') => (b'\n', 1),
+            Some(b'r') => (b'\r', 1),
+            Some(b't') => (b'\t', 1),
+            Some(b'e') => (0x1b, 1),
+            Some(b'\\') => (b'\\', 1),
+            Some(b'x') => {
+                let hex = escape.get(1..3).filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()));
+                match hex {
+                    Some(hex) => (u8::from_str_radix(hex, 16).unwrap_or_default(), 3),
+                    None => return Err(format!("invalid escape \\{}", escape.chars().take(3).collect::<String>())),
+                }
+            }
+            Some(_) => return Err(format!("invalid escape \\{}", escape.chars().next().unwrap_or_default())),
+            None => return Err("trailing \\".into()),
+        };
+        out.push(byte);
+        rest = &escape[len..];
+    }
+    out.extend_from_slice(rest.as_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("yes\\n").unwrap(), b"yes\n");
+        assert_eq!(unescape("a\\tb\\\\n\\r").unwrap(), b"a\tb\\n\r");
+        assert_eq!(unescape("\\x03\\e[A").unwrap(), b"\x03\x1b[A");
+        assert_eq!(unescape("caf\u{e9}").unwrap(), "caf\u{e9}".as_bytes());
+        assert!(unescape("\\q").unwrap_err().contains("\\q"));
+        assert!(unescape("\\x4").is_err());
+        assert!(unescape("end\\").is_err());
+    }
+
+    #[test]
+    fn test_to_output() {
+        assert_eq!(to_output(b"a\r\nb\r\r\n\rc\r"), b"a\nb\r\n\rc\r");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_spawn_on_pty() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "test -t 0 && test -t 1 && read line && echo got:$line"]);
+        let Ok((mut child, session)) = spawn(cmd) else {
+            // No ptys available (e.g. a minimal container)
+            return;
+        };
+        session.send(b"hello\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let found = session.transcript().wait_for(0, deadline, |data| {
+            data.windows(9).position(|w| w == b"got:hello")
+        });
+        assert!(found.is_ok(), "{:?}", String::from_utf8_lossy(&session.transcript().contents()));
+        assert!(child.wait().unwrap().success());
+        assert_eq!(session.transcript().finish(), b"hello\ngot:hello\n");
+    }
+}
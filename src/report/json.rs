@@ -0,0 +1,178 @@
+//! JSON-lines event stream
+//!
+//! Modeled on `cargo test -- --format json`: one JSON object per line,
+//! a `suite` start event, `test` start/finish events, and a final `suite`
+//! summary.
+//!
+//! ```text
+//! {"type":"suite","event":"started","test_count":2}
+//! {"type":"test","event":"started","name":"basic","file":"tests/basic.txtar"}
+//! {"type":"test","event":"passed","name":"basic","file":"tests/basic.txtar","exec_time":0.012,"log":"..."}
+//! {"type":"test","event":"started","name":"bad","file":"tests/bad.txtar"}
+//! {"type":"test","event":"failed","name":"bad","file":"tests/bad.txtar","exec_time":0.003,"error_kind":"comparison_failed","line":3,"message":"...","log":"..."}
+//! {"type":"suite","event":"failed","passed":1,"failed":1,"skipped":0,"exec_time":0.016}
+//! ```
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use serde_json::{json, Value};
+use super::Reporter;
+use crate::runner::{TestCaseResult, TestResult};
+
+/// A `Reporter` that writes one JSON event per line to `W`.
+///
+/// Writes are serialized through a mutex, so events from parallel
+/// workers never interleave within a line.
+pub struct JsonReporter<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonReporter<W> {
+    /// Create a reporter writing to `out`
+    pub fn new(out: W) -> Self {
+        Self { out: Mutex::new(out) }
+    }
+
+    fn emit(&self, event: Value) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Reporting must never abort the run; a closed pipe just drops events
+        let _ = writeln!(out, "{}", event);
+        let _ = out.flush();
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonReporter<W> {
+    fn suite_started(&self, total: usize) {
+        self.emit(json!({
+            "type": "suite",
+            "event": "started",
+            "test_count": total,
+        }));
+    }
+
+    fn test_started(&self, name: &str, file: &Path) {
+        self.emit(json!({
+            "type": "test",
+            "event": "started",
+            "name": name,
+            "file": file.to_string_lossy(),
+        }));
+    }
+
+    fn test_finished(&self, case: &TestCaseResult) {
+        let event = if case.skipped {
+            "skipped"
+        } else if case.passed {
+            "passed"
+        } else {
+            "failed"
+        };
+        let mut obj = json!({
+            "type": "test",
+            "event": event,
+            "name": case.name,
+            "file": case.file.to_string_lossy(),
+            "exec_time": case.duration.as_secs_f64(),
+            "log": case.log,
+        });
+        if let Some(ref kind) = case.error_kind {
+            obj["error_kind"] = json!(kind.as_str());
+        }
+        if let Some(line) = case.error_line {
+            obj["line"] = json!(line);
+        }
+        if let Some(ref error) = case.error {
+            obj["message"] = json!(error);
+        }
+        if let Some(ref workdir) = case.workdir {
+            obj["workdir"] = json!(workdir.to_string_lossy());
+        }
+        self.emit(obj);
+    }
+
+    fn suite_finished(&self, result: &TestResult) {
+        self.emit(json!({
+            "type": "suite",
+            "event": if result.all_passed() { "ok" } else { "failed" },
+            "passed": result.passed_count(),
+            "failed": result.failed_count(),
+            "skipped": result.skipped_count(),
+            "exec_time": result.duration.as_secs_f64(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A writer whose contents outlive the runner that owns the reporter
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_events() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a_pass.txtar"), "echo hi\nstdout hi\n").unwrap();
+        std::fs::write(dir.path().join("b_fail.txtar"), "echo hi\nstdout bye\n").unwrap();
+        std::fs::write(dir.path().join("c_skip.txtar"), "skip 'not here'\n").unwrap();
+
+        let buf = SharedBuf::default();
+        let result = crate::runner::run(dir.path())
+            .jobs(1)
+            .reporter(Box::new(JsonReporter::new(buf.clone())))
+            .run()
+            .unwrap();
+        assert_eq!(result.failed_count(), 1);
+
+        let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = out.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let kinds: Vec<(&str, &str)> = events
+            .iter()
+            .map(|e| (e["type"].as_str().unwrap(), e["event"].as_str().unwrap()))
+            .collect();
+        assert_eq!(kinds, vec![
+            ("suite", "started"),
+            ("test", "started"),
+            ("test", "passed"),
+            ("test", "started"),
+            ("test", "failed"),
+            ("test", "started"),
+            ("test", "skipped"),
+            ("suite", "failed"),
+        ]);
+
+        assert_eq!(events[0]["test_count"], 3);
+        assert_eq!(events[1]["name"], "a_pass");
+        assert!(events[1]["file"].as_str().unwrap().ends_with("a_pass.txtar"));
+        assert!(events[2]["exec_time"].is_f64());
+        assert!(events[2].get("message").is_none());
+
+        let failed = &events[4];
+        assert_eq!(failed["name"], "b_fail");
+        assert_eq!(failed["error_kind"], "pattern_mismatch");
+        assert_eq!(failed["line"], 2);
+        assert!(failed["message"].as_str().unwrap().contains("bye"));
+        assert!(failed["log"].as_str().unwrap().contains("> stdout bye"));
+
+        assert_eq!(events[6]["name"], "c_skip");
+        assert!(events[6]["message"].as_str().unwrap().contains("not here"));
+
+        let summary = &events[7];
+        assert_eq!(summary["passed"], 1);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["skipped"], 1);
+    }
+}
@@ -0,0 +1,154 @@
+//! JUnit XML report
+//!
+//! Emits the de-facto JUnit schema understood by Jenkins, GitLab, GitHub
+//! Actions and friends: one `<testcase>` per script, with `<failure>`,
+//! `<skipped>` and `<system-out>` children.
+
+use std::fmt::Write;
+use crate::runner::{TestCaseResult, TestResult};
+
+/// Serialize a `TestResult` to a JUnit XML document.
+///
+/// `suite` names the single `<testsuite>` element (typically the test
+/// directory). Failures carry the script error (`file:line: message`),
+/// skips carry the skip reason, and the execution log goes to `<system-out>`.
+pub fn junit_xml(result: &TestResult, suite: &str) -> String {
+    let tests = result.cases.len();
+    let failures = result.failed_count();
+    let skipped = result.skipped_count();
+    let time = seconds(result.duration);
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">",
+        tests, failures, skipped, time,
+    );
+    let _ = writeln!(
+        out,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">",
+        escape(suite), tests, failures, skipped, time,
+    );
+    for case in &result.cases {
+        write_case(&mut out, case);
+    }
+    out.push_str("  </testsuite>\n");
+    out.push_str("</testsuites>\n");
+    out
+}
+
+fn write_case(out: &mut String, case: &TestCaseResult) {
+    let classname = case.file.parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let _ = write!(
+        out,
+        "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" time=\"{}\"",
+        escape(&case.name),
+        escape(&classname),
+        escape(&case.file.to_string_lossy()),
+        seconds(case.duration),
+    );
+
+    let failed = !case.passed && !case.skipped;
+    if !failed && !case.skipped && case.log.is_empty() {
+        out.push_str("/>\n");
+        return;
+    }
+    out.push_str(">\n");
+
+    if case.skipped {
+        let _ = writeln!(
+            out,
+            "      <skipped message=\"{}\"/>",
+            escape(case.error.as_deref().unwrap_or("")),
+        );
+    } else if failed {
+        let error = case.error.as_deref().unwrap_or("test failed");
+        let message = error.lines().next().unwrap_or("");
+        let _ = writeln!(
+            out,
+            "      <failure message=\"{}\">{}</failure>",
+            escape(message),
+            escape(error),
+        );
+    }
+
+    if !case.log.is_empty() {
+        let _ = writeln!(out, "      <system-out>{}</system-out>", escape(&case.log));
+    }
+    out.push_str("    </testcase>\n");
+}
+
+/// Format a duration as fractional seconds, as JUnit expects.
+fn seconds(d: std::time::Duration) -> String {
+    format!("{:.3}", d.as_secs_f64())
+}
+
+/// Escape text for use in XML attributes and character data.
+///
+/// Characters that are not allowed in XML 1.0 at all (most C0 controls,
+/// which show up in subprocess output) are dropped.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn case(name: &str, passed: bool, skipped: bool, error: Option<&str>, log: &str) -> TestCaseResult {
+        TestCaseResult {
+            name: name.into(),
+            file: PathBuf::from(format!("tests/{}.txtar", name)),
+            passed,
+            skipped,
+            error: error.map(String::from),
+            error_kind: None,
+            error_line: None,
+            log: log.into(),
+            duration: Duration::from_millis(1500),
+            workdir: None,
+        }
+    }
+
+    #[test]
+    fn test_junit_counts_and_elements() {
+        let result = TestResult {
+            cases: vec![
+                case("ok", true, false, None, ""),
+                case("bad", false, false, Some("tests/bad.txtar:3: cmp stdout want: stdout and want differ"), "> cmp stdout want\n"),
+                case("later", true, true, Some("not on this OS"), ""),
+            ],
+            duration: Duration::from_secs(2),
+        };
+        let xml = junit_xml(&result, "tests");
+
+        assert!(xml.contains("<testsuite name=\"tests\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"2.000\">"));
+        assert!(xml.contains("<testcase name=\"ok\" classname=\"tests\" file=\"tests/ok.txtar\" time=\"1.500\"/>"));
+        assert!(xml.contains("<failure message=\"tests/bad.txtar:3: cmp stdout want: stdout and want differ\">"));
+        assert!(xml.contains("<system-out>&gt; cmp stdout want\n</system-out>"));
+        assert!(xml.contains("<skipped message=\"not on this OS\"/>"));
+    }
+
+    #[test]
+    fn test_junit_escape() {
+        assert_eq!(escape("a<b & \"c\"\x1b[0m"), "a&lt;b &amp; &quot;c&quot;[0m");
+    }
+}
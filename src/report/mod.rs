@@ -0,0 +1,32 @@
+//! Test result reporters
+//!
+//! Serializes a `TestResult` into machine-readable formats for CI systems,
+//! and streams progress events from the runner via the `Reporter` trait.
+
+mod junit;
+mod json;
+
+use std::path::Path;
+use crate::runner::{TestCaseResult, TestResult};
+
+pub use junit::junit_xml;
+pub use json::JsonReporter;
+
+/// Receives progress events from `TestRunner::run_all`.
+///
+/// With `jobs > 1`, `test_started`/`test_finished` are called from worker
+/// threads, so events for different tests may arrive interleaved and out
+/// of discovery order. All methods default to no-ops.
+pub trait Reporter: Send + Sync {
+    /// Called once before any test runs, with the number of discovered tests
+    fn suite_started(&self, _total: usize) {}
+
+    /// Called when a test starts
+    fn test_started(&self, _name: &str, _file: &Path) {}
+
+    /// Called when a test has finished (passed, failed or skipped)
+    fn test_finished(&self, _case: &TestCaseResult) {}
+
+    /// Called once after all tests have finished
+    fn suite_finished(&self, _result: &TestResult) {}
+}
@@ -4,9 +4,15 @@
 //! creates temp dirs, extracts files, runs scripts, and reports results.
 
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
+use crate::ast::{FuncDef, StatementKind};
 use crate::engine::Engine;
-use crate::state::State;
+use crate::include::load_script;
+use crate::error::{ErrorKind, ScriptError};
+use crate::lint::Diagnostic;
+use crate::report::Reporter;
+use crate::state::{GoldenUpdate, State};
 
 /// Configuration for the test runner
 pub struct RunConfig {
@@ -18,12 +24,22 @@ pub struct RunConfig {
     pub workdir_root: Option<PathBuf>,
     /// Preserve working directories after test (for debugging)
     pub preserve_work: bool,
-    /// Setup function called before each test
-    pub setup: Option<Box<dyn Fn(&mut SetupEnv) -> Result<(), Box<dyn std::error::Error>> + Send>>,
+    /// Setup function called before each test.
+    /// Must be `Sync` because tests may run concurrently (see `jobs`).
+    pub setup: Option<Box<dyn Fn(&mut SetupEnv) -> Result<(), Box<dyn std::error::Error>> + Send + Sync>>,
     /// Verbose mode — print script execution log
     pub verbose: bool,
     /// File extensions to scan (default: [".txtar"])
     pub extensions: Vec<String>,
+    /// Number of tests to run concurrently (default: 1).
+    /// `0` means one worker per available CPU.
+    pub jobs: usize,
+    /// Update mode — a failing `cmp`/`cmpenv` whose second operand is an
+    /// archive file or heredoc rewrites it in the original txtar file
+    pub update: bool,
+    /// Per-script deadline; a script still running after this long fails
+    /// with `ErrorKind::Timeout` and its running subprocess is killed
+    pub timeout: Option<Duration>,
 }
 
 /// Environment available during setup
@@ -44,10 +60,21 @@ impl Default for RunConfig {
             setup: None,
             verbose: false,
             extensions: vec![".txtar".into()],
+            jobs: 1,
+            update: false,
+            timeout: None,
         }
     }
 }
 
+impl RunConfig {
+    /// Whether `TESTSCRIPT_UPDATE` asks for update mode: set to anything
+    /// but empty or `0`
+    pub fn update_from_env() -> bool {
+        std::env::var("TESTSCRIPT_UPDATE").is_ok_and(|v| !v.is_empty() && v != "0")
+    }
+}
+
 /// Result of running all tests
 #[derive(Debug)]
 pub struct TestResult {
@@ -103,6 +130,10 @@ pub struct TestCaseResult {
     pub skipped: bool,
     /// Error message if failed
     pub error: Option<String>,
+    /// Kind of the script error, if the script failed or was skipped
+    pub error_kind: Option<ErrorKind>,
+    /// Script line the error occurred on, if known
+    pub error_line: Option<usize>,
     /// Execution log
     pub log: String,
     /// Duration
@@ -115,6 +146,7 @@ pub struct TestCaseResult {
 pub struct TestRunner {
     engine: Engine,
     config: RunConfig,
+    reporter: Option<Box<dyn Reporter>>,
 }
 
 impl TestRunner {
@@ -123,12 +155,13 @@ impl TestRunner {
         Self {
             engine: Engine::new(),
             config,
+            reporter: None,
         }
     }
 
     /// Create a new runner with a custom engine
     pub fn with_engine(engine: Engine, config: RunConfig) -> Self {
-        Self { engine, config }
+        Self { engine, config, reporter: None }
     }
 
     /// Get mutable reference to the engine (for registering custom commands)
@@ -136,6 +169,11 @@ impl TestRunner {
         &mut self.engine
     }
 
+    /// Set a reporter that receives progress events from `run_all`
+    pub fn set_reporter(&mut self, reporter: Box<dyn Reporter>) {
+        self.reporter = Some(reporter);
+    }
+
     /// Discover test files in the configured directory
     pub fn discover(&self) -> Result<Vec<PathBuf>, std::io::Error> {
         let mut files = Vec::new();
@@ -192,21 +230,140 @@ impl TestRunner {
         }
     }
 
-    /// Run all discovered tests
+    /// Statically check all discovered tests without running them.
+    /// Returns the files with problems, in discovery order.
+    pub fn check_all(&self) -> Result<Vec<(PathBuf, Vec<Diagnostic>)>, std::io::Error> {
+        let mut results = Vec::new();
+        for file in self.discover()? {
+            let data = std::fs::read_to_string(&file)?;
+            let diags = match emx_txtar::Decoder::new().decode(&data) {
+                Ok(archive) => self.check_with_includes(&file, archive),
+                Err(e) => vec![Diagnostic { line: 1, message: format!("failed to parse txtar: {}", e) }],
+            };
+            if !diags.is_empty() {
+                results.push((file, diags));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Check an archive, counting the files of its includes as present.
+    /// A failing include is reported on the line of this file leading to it.
+    fn check_with_includes(&self, file: &Path, mut archive: emx_txtar::Archive) -> Vec<Diagnostic> {
+        let error = match load_script(file, &archive.comment) {
+            Ok(loaded) => {
+                archive.files.splice(0..0, loaded.included_files);
+                let statements = loaded.script.all_statements();
+                let funcs: Vec<&FuncDef> = statements
+                    .iter()
+                    .filter_map(|stmt| match &stmt.kind {
+                        StatementKind::Func(func) => Some(func),
+                        _ => None,
+                    })
+                    .collect();
+                return self.engine.check_archive_with(&archive, &funcs);
+            }
+            Err(e) => e,
+        };
+
+        let mut diags = self.engine.check_archive(&archive);
+        let diag = match error.trace.last() {
+            Some(frame) => Diagnostic {
+                line: frame.line,
+                message: error.to_string().lines().next().unwrap_or_default().to_string(),
+            },
+            None => Diagnostic { line: error.line.unwrap_or(1), message: error.message },
+        };
+        // Syntax errors in this script are already reported
+        if !diags.iter().any(|d| d.line == diag.line) {
+            diags.push(diag);
+            diags.sort_by_key(|d| d.line);
+        }
+        diags
+    }
+
+    /// Run all discovered tests.
+    ///
+    /// With `jobs > 1` tests run on a worker pool; results are still
+    /// returned in discovery (sorted) order.
     pub fn run_all(&self) -> Result<TestResult, std::io::Error> {
         let start = Instant::now();
         let files = self.discover()?;
 
-        let mut cases = Vec::new();
-        for file in &files {
-            let result = self.run_one(file);
-            cases.push(result);
+        if let Some(ref reporter) = self.reporter {
+            reporter.suite_started(files.len());
         }
 
-        Ok(TestResult {
+        let jobs = self.worker_count(files.len());
+        let cases = if jobs <= 1 {
+            files.iter().map(|file| self.run_reported(file)).collect()
+        } else {
+            self.run_parallel(&files, jobs)
+        };
+
+        let result = TestResult {
             cases,
             duration: start.elapsed(),
-        })
+        };
+        if let Some(ref reporter) = self.reporter {
+            reporter.suite_finished(&result);
+        }
+        Ok(result)
+    }
+
+    /// Run a single test file, notifying the reporter before and after.
+    fn run_reported(&self, file: &Path) -> TestCaseResult {
+        if let Some(ref reporter) = self.reporter {
+            reporter.test_started(&test_name(file), file);
+        }
+        let case = self.run_one(file);
+        if let Some(ref reporter) = self.reporter {
+            reporter.test_finished(&case);
+        }
+        case
+    }
+
+    /// Number of workers to use for `count` test files.
+    fn worker_count(&self, count: usize) -> usize {
+        let jobs = if self.config.jobs == 0 {
+            std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        } else {
+            self.config.jobs
+        };
+        jobs.min(count)
+    }
+
+    /// Run test files on `jobs` worker threads.
+    ///
+    /// Each test owns its `State` and working directory, so workers share
+    /// nothing but the (immutable) engine and config. Workers pull the next
+    /// file index from a shared counter; results are re-sorted by index.
+    fn run_parallel(&self, files: &[PathBuf], jobs: usize) -> Vec<TestCaseResult> {
+        let next = AtomicUsize::new(0);
+
+        let mut indexed: Vec<(usize, TestCaseResult)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let idx = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(file) = files.get(idx) else { break };
+                            done.push((idx, self.run_reported(file)));
+                        }
+                        done
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("test worker panicked"))
+                .collect()
+        });
+
+        indexed.sort_by_key(|(idx, _)| *idx);
+        indexed.into_iter().map(|(_, case)| case).collect()
     }
 
     /// Count the number of tests that would be run
@@ -219,10 +376,7 @@ impl TestRunner {
     /// Run a single test file
     pub fn run_one(&self, file: &Path) -> TestCaseResult {
         let start = Instant::now();
-        let name = file.file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+        let name = test_name(file);
 
         // Phase 1: parse + prepare
         let (archive, tmpdir) = match self.prepare_test(file, &name) {
@@ -234,6 +388,8 @@ impl TestRunner {
                     passed: false,
                     skipped: false,
                     error: Some(error),
+                    error_kind: None,
+                    error_line: None,
                     log: String::new(),
                     duration: start.elapsed(),
                     workdir: None,
@@ -243,9 +399,29 @@ impl TestRunner {
 
         let workdir = tmpdir.path().to_path_buf();
         let mut state = State::new(workdir.clone());
+        state.update = self.config.update;
 
         // Phase 2: extract + setup + execute
-        let (passed, skipped, error) = self.execute_test(file, &archive, &mut state, &workdir);
+        let (mut passed, skipped, mut script_error) = self.execute_test(file, &archive, &mut state, &workdir);
+
+        // Phase 3: write golden-file updates back to the archive
+        if passed && !state.golden_updates.is_empty() {
+            match write_golden_updates(file, &state.golden_updates) {
+                Ok(()) => state.logf(&format!("updated {}", file.display())),
+                Err(e) => {
+                    passed = false;
+                    script_error = Some(ScriptError::new(
+                        ErrorKind::Io,
+                        format!("failed to update {}: {}", file.display(), e),
+                    ));
+                }
+            }
+        }
+
+        // Skip reasons are reported bare; failures with their location
+        let error = script_error.as_ref().map(|e| {
+            if skipped { e.message.clone() } else { e.to_string() }
+        });
 
         // Preserve workdir on failure or if configured
         let preserved_workdir = if self.config.preserve_work || !passed {
@@ -262,6 +438,8 @@ impl TestRunner {
             passed,
             skipped,
             error,
+            error_kind: script_error.as_ref().map(|e| e.kind.clone()),
+            error_line: script_error.as_ref().and_then(|e| e.line),
             log: state.log,
             duration: start.elapsed(),
             workdir: preserved_workdir,
@@ -288,16 +466,21 @@ impl TestRunner {
     }
 
     /// Extract files, run setup, and execute the script. Returns (passed, skipped, error).
+    /// For skipped tests the error carries the skip reason.
     fn execute_test(
         &self,
         file: &Path,
         archive: &emx_txtar::Archive,
         state: &mut State,
         workdir: &Path,
-    ) -> (bool, bool, Option<String>) {
-        // Extract archive files
+    ) -> (bool, bool, Option<ScriptError>) {
+        // Extract archive files; included archives' files are extracted
+        // as their `include` lines run
         if let Err(e) = state.extract_files(archive) {
-            return (false, false, Some(format!("failed to extract files: {}", e)));
+            return (false, false, Some(ScriptError::new(
+                ErrorKind::Io,
+                format!("failed to extract files: {}", e),
+            )));
         }
 
         // Run setup
@@ -307,7 +490,10 @@ impl TestRunner {
                 env: Vec::new(),
             };
             if let Err(e) = setup(&mut env) {
-                return (false, false, Some(format!("setup failed: {}", e)));
+                return (false, false, Some(ScriptError::new(
+                    ErrorKind::Other,
+                    format!("setup failed: {}", e),
+                )));
             }
             for (k, v) in env.env {
                 state.setenv(k, v);
@@ -315,14 +501,16 @@ impl TestRunner {
         }
 
         // Execute the script
-        let script = &archive.comment;
-        let filename = file.to_string_lossy().to_string();
+        state.deadline = self.config.timeout.map(|t| Instant::now() + t);
+
+        let result = self.engine.execute(state, &archive.comment, &file.to_string_lossy());
+        state.kill_background();
 
-        match self.engine.execute(state, script, &filename) {
+        match result {
             Ok(()) => (true, false, None),
-            Err(e) if e.is_skip() => (true, true, Some(e.message.clone())),
+            Err(e) if e.is_skip() => (true, true, Some(e)),
             Err(e) if e.is_stop() => (true, false, None),
-            Err(e) => (false, false, Some(e.to_string())),
+            Err(e) => (false, false, Some(e)),
         }
     }
 
@@ -341,10 +529,78 @@ impl TestRunner {
     }
 }
 
+/// Test name: the file name without extension
+fn test_name(file: &Path) -> String {
+    file.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Apply golden-file updates to the txtar archive at `file` and rewrite it.
+///
+/// The archive is re-read and re-encoded through `emx_txtar`; only the
+/// updated files and heredoc bodies change. The encoded result is decoded
+/// again and checked before anything is written.
+fn write_golden_updates(file: &Path, updates: &[GoldenUpdate]) -> Result<(), String> {
+    let data = std::fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let decoder = emx_txtar::Decoder::new();
+    let mut archive = decoder.decode(&data).map_err(|e| e.to_string())?;
+
+    // Heredoc bodies live in the script: splice bottom-up so earlier
+    // line ranges stay valid.
+    let mut heredocs: Vec<_> = updates
+        .iter()
+        .filter_map(|u| match u {
+            GoldenUpdate::Heredoc { lines, content } => Some((lines.clone(), content)),
+            GoldenUpdate::File { .. } => None,
+        })
+        .collect();
+    if !heredocs.is_empty() {
+        heredocs.sort_by_key(|(lines, _)| std::cmp::Reverse(lines.start));
+        heredocs.dedup_by_key(|(lines, _)| lines.start);
+
+        let eol = if archive.comment.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut script: Vec<String> = archive.comment.split_inclusive('\n').map(String::from).collect();
+        for (lines, content) in heredocs {
+            if lines.end > script.len() {
+                return Err(format!("heredoc lines {:?} out of range", lines));
+            }
+            let body = content.lines().map(|l| format!("{}{}", l, eol));
+            script.splice(lines, body);
+        }
+        archive.comment = script.concat();
+    }
+
+    for update in updates {
+        if let GoldenUpdate::File { name, content } = update {
+            let entry = archive.files.iter_mut().find(|f| &f.name == name)
+                .ok_or_else(|| format!("archive file {} not found", name))?;
+            entry.data = content.clone().into();
+        }
+    }
+
+    let encoded = emx_txtar::Encoder::new().encode(&archive);
+
+    // Round-trip check: the new archive must decode to exactly what we built
+    let check = decoder.decode(&encoded).map_err(|e| e.to_string())?;
+    let same_files = check.files.len() == archive.files.len()
+        && check.files.iter().zip(&archive.files).all(|(a, b)| {
+            let (a_data, b_data): (&[u8], &[u8]) = (a.data.as_ref(), b.data.as_ref());
+            a.name == b.name && a_data == b_data
+        });
+    if check.comment != archive.comment || !same_files {
+        return Err("updated archive does not round-trip".into());
+    }
+
+    std::fs::write(file, encoded).map_err(|e| e.to_string())
+}
+
 /// Builder API for convenient test runner construction
 pub struct TestRunnerBuilder {
     config: RunConfig,
     engine: Option<Engine>,
+    reporter: Option<Box<dyn Reporter>>,
 }
 
 impl TestRunnerBuilder {
@@ -356,6 +612,7 @@ impl TestRunnerBuilder {
                 ..Default::default()
             },
             engine: None,
+            reporter: None,
         }
     }
 
@@ -389,19 +646,45 @@ impl TestRunnerBuilder {
         self
     }
 
+    /// Set the number of tests to run concurrently (0 = one per CPU)
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.config.jobs = jobs;
+        self
+    }
+
+    /// Fail scripts that run longer than `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Rewrite golden files in the archive when `cmp`/`cmpenv` fails
+    pub fn update(mut self, update: bool) -> Self {
+        self.config.update = update;
+        self
+    }
+
     /// Use a custom engine
     pub fn engine(mut self, engine: Engine) -> Self {
         self.engine = Some(engine);
         self
     }
 
+    /// Report progress events to `reporter`
+    pub fn reporter(mut self, reporter: Box<dyn Reporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
     /// Build and return the runner
     pub fn build(self) -> TestRunner {
-        if let Some(engine) = self.engine {
+        let mut runner = if let Some(engine) = self.engine {
             TestRunner::with_engine(engine, self.config)
         } else {
             TestRunner::new(self.config)
-        }
+        };
+        runner.reporter = self.reporter;
+        runner
     }
 
     /// Build and run all tests
@@ -438,6 +721,7 @@ pub fn run_and_assert_with(dir: impl Into<PathBuf>, customize: impl FnOnce(&mut
         dir,
         verbose: std::env::var("TESTSCRIPT_VERBOSE").is_ok(),
         preserve_work: std::env::var("TESTSCRIPT_WORK").is_ok(),
+        update: RunConfig::update_from_env(),
         ..Default::default()
     };
 
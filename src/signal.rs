@@ -0,0 +1,89 @@
+//! Signal names and delivery for background jobs (`kill`, `exitcode SIGINT`)
+
+use std::process::Child;
+
+/// The default signal for `kill`
+#[cfg(unix)]
+pub const SIGKILL: i32 = libc::SIGKILL;
+#[cfg(not(unix))]
+pub const SIGKILL: i32 = 9;
+
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+];
+
+// Only a forced kill can be delivered on other platforms
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[("KILL", SIGKILL)];
+
+/// Parse a signal given as `INT`, `SIGINT` or a number.
+pub fn parse(s: &str) -> Option<i32> {
+    if let Ok(n) = s.parse::<i32>() {
+        return SIGNALS.iter().any(|&(_, sig)| sig == n).then_some(n);
+    }
+    let upper = s.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS.iter().find(|(name, _)| *name == bare).map(|&(_, sig)| sig)
+}
+
+/// Display name of a signal, e.g. `SIGINT`.
+pub fn name(sig: i32) -> String {
+    match SIGNALS.iter().find(|&&(_, s)| s == sig) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => format!("signal {}", sig),
+    }
+}
+
+/// Send `sig` to a child process.
+///
+/// A background job leads its own process group (a `-pty` one its own
+/// session), and the whole group is signalled, so whatever the program
+/// started (e.g. the children of `sh -c`) goes with it.
+pub fn send(child: &mut Child, sig: i32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: plain syscalls on the pid of a child we haven't reaped yet.
+        // No group has that id unless the child leads one.
+        if unsafe { libc::kill(-pid, sig) == -1 && libc::kill(pid, sig) == -1 } {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        if sig == SIGKILL {
+            return child.kill();
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} is not supported on this platform", name(sig)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal_names() {
+        assert_eq!(parse("KILL"), Some(SIGKILL));
+        assert_eq!(parse("SIGKILL"), Some(SIGKILL));
+        assert_eq!(parse("sigkill"), Some(SIGKILL));
+        assert_eq!(parse(&SIGKILL.to_string()), Some(SIGKILL));
+        assert_eq!(parse("BOGUS"), None);
+        assert_eq!(name(SIGKILL), "SIGKILL");
+    }
+}
@@ -3,9 +3,14 @@
 //! Holds mutable per-run state: working directory, environment variables,
 //! stdout/stderr buffers, and log.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use crate::engine::WaitHandle;
+use std::sync::Arc;
+use crate::ast::FuncDef;
+use crate::engine::{StageStatus, WaitHandle};
+use crate::pty::PtySession;
 
 /// A background command waiting to be harvested by `wait`.
 pub struct BackgroundCmd {
@@ -19,6 +24,38 @@ pub struct BackgroundCmd {
     pub negate: bool,
     /// Whether the command may fail (? prefix)
     pub may_fail: bool,
+    /// Job name given with `exec -name=`, used by `wait` and `kill`
+    pub job: Option<String>,
+    /// Signal sent by `kill`; dying from it is not a failure
+    pub signaled: Option<i32>,
+}
+
+impl BackgroundCmd {
+    /// Name used in messages: the job name, or the command name
+    pub fn label(&self) -> &str {
+        self.job.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// A `func` macro defined by the running script
+#[derive(Debug)]
+pub struct ScriptFunc {
+    /// The definition
+    pub def: FuncDef,
+    /// File the definition is in, for error locations
+    pub file: String,
+}
+
+/// A golden-file rewrite recorded by `cmp`/`cmpenv` in update mode.
+///
+/// The runner applies these to the original txtar archive once the
+/// script has finished.
+#[derive(Debug, Clone)]
+pub enum GoldenUpdate {
+    /// Replace the data of the named archive file
+    File { name: String, content: String },
+    /// Replace the body of a heredoc: script lines `lines` (0-based, end-exclusive)
+    Heredoc { lines: Range<usize>, content: String },
 }
 
 /// Mutable state for a single script execution
@@ -31,12 +68,18 @@ pub struct State {
     env: Vec<(String, String)>,
     /// Index for O(1) lookup by key → position in `env` vec
     env_index: HashMap<String, usize>,
-    /// Last command's stdout
-    pub stdout: String,
-    /// Last command's stderr
-    pub stderr: String,
-    /// Last command's exit code
+    /// Last command's stdout, as raw bytes (see `stdout_text`)
+    pub stdout: Vec<u8>,
+    /// Last command's stderr, as raw bytes (see `stderr_text`)
+    pub stderr: Vec<u8>,
+    /// Exit code of the last waited-for subprocess (`None` if it was killed
+    /// or the last handle was not a process); readable in scripts as `$?`
     pub exit_code: Option<i32>,
+    /// Signal that terminated the last waited-for subprocess
+    pub exit_signal: Option<i32>,
+    /// Status of each stage of the last waited-for `exec` (one entry unless
+    /// it was a pipeline), checked by `exitcode code...`
+    pub pipe_status: Vec<StageStatus>,
     /// Execution log
     pub log: String,
     /// Background commands waiting to be harvested
@@ -45,6 +88,26 @@ pub struct State {
     heredoc_files: HashMap<String, String>,
     /// Counter for generating unique heredoc file names
     heredoc_counter: usize,
+    /// End marker and script lines of each heredoc body, by virtual path
+    heredoc_sources: HashMap<String, (String, Range<usize>)>,
+    /// Extracted archive files: resolved path → archive file name
+    archive_files: HashMap<PathBuf, String>,
+    /// Update mode: failing `cmp`/`cmpenv` rewrite their golden operand
+    pub update: bool,
+    /// Golden-file rewrites recorded in update mode
+    pub golden_updates: Vec<GoldenUpdate>,
+    /// Script-level deadline; commands still running at this point are killed
+    pub deadline: Option<std::time::Instant>,
+    /// Standard input for the next `exec`, set by the `stdin` command
+    pub stdin: Option<Vec<u8>>,
+    /// Terminal of the last `exec -pty`, driven by `expect` and `send`
+    pub pty: Option<PtySession>,
+    /// `func` macros defined so far, by name
+    funcs: HashMap<String, Arc<ScriptFunc>>,
+    /// Arguments of the `func` calls in progress, innermost last
+    call_args: Vec<Vec<String>>,
+    /// Canonical paths of the archives being run, outermost first
+    includes: Vec<PathBuf>,
 }
 
 impl State {
@@ -56,13 +119,25 @@ impl State {
             pwd,
             env: Vec::new(),
             env_index: HashMap::new(),
-            stdout: String::new(),
-            stderr: String::new(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
             exit_code: None,
+            exit_signal: None,
+            pipe_status: Vec::new(),
             log: String::new(),
             background: Vec::new(),
             heredoc_files: HashMap::new(),
             heredoc_counter: 0,
+            heredoc_sources: HashMap::new(),
+            archive_files: HashMap::new(),
+            update: false,
+            golden_updates: Vec::new(),
+            deadline: None,
+            stdin: None,
+            pty: None,
+            funcs: HashMap::new(),
+            call_args: Vec::new(),
+            includes: Vec::new(),
         };
 
         // Go-compatible: inherit all parent environment variables by default.
@@ -90,6 +165,47 @@ impl State {
         state
     }
 
+    /// Record the last exit status and mirror the code into the `$?`
+    /// pseudo-variable (empty when the process died from a signal).
+    pub fn set_exit_status(&mut self, code: Option<i32>, signal: Option<i32>) {
+        self.exit_code = code;
+        self.exit_signal = signal;
+        self.setenv("?", code.map(|c| c.to_string()).unwrap_or_default());
+    }
+
+    /// Kill background jobs not waited for at script end and log what they
+    /// printed, so no process outlives its test.
+    pub fn kill_background(&mut self) {
+        for mut bg in std::mem::take(&mut self.background) {
+            let label = bg.label().to_string();
+            if let WaitHandle::Thread(handle) = &bg.handle {
+                // A thread (e.g. `sleep &`) is left to finish detached
+                if !handle.is_finished() {
+                    self.logf(&format!("[background] {} still running at script end", label));
+                }
+                continue;
+            }
+
+            let server = matches!(bg.handle, WaitHandle::Http(_));
+            let _ = bg.handle.signal(crate::signal::SIGKILL);
+            let output = bg.handle.wait(None);
+            // Only a job that was still running dies from this SIGKILL
+            let killed = output.stages.iter().any(|s| s.signal == Some(crate::signal::SIGKILL))
+                && bg.signaled != Some(crate::signal::SIGKILL);
+            if server || killed {
+                self.logf(&format!("[background] {} still running at script end, killed", label));
+            } else {
+                self.logf(&format!("[background] {} not waited for", label));
+            }
+            if !output.stdout.is_empty() {
+                self.logf(&format!("[stdout]\n{}", String::from_utf8_lossy(&output.stdout)));
+            }
+            if !output.stderr.is_empty() {
+                self.logf(&format!("[stderr]\n{}", String::from_utf8_lossy(&output.stderr)));
+            }
+        }
+    }
+
     /// Set an environment variable.
     ///
     /// On Windows, environment variable names are case-insensitive
@@ -118,7 +234,7 @@ impl State {
     }
 
     /// Find the canonical key name in env_index (case-insensitive on Windows).
-    fn find_env_key(&self, key: &str) -> Option<&str> {
+    fn find_env_key<'a>(&'a self, key: &'a str) -> Option<&'a str> {
         #[cfg(windows)]
         {
             for k in self.env_index.keys() {
@@ -144,7 +260,7 @@ impl State {
     /// subprocess environment (Go stores them in envMap but not in env slice).
     pub fn environ(&self) -> Vec<(&str, &str)> {
         self.env.iter()
-            .filter(|(k, _)| k != "/" && k != ":")
+            .filter(|(k, _)| k != "/" && k != ":" && k != "?")
             .map(|(k, v)| (k.as_str(), v.as_str()))
             .collect()
     }
@@ -228,7 +344,20 @@ impl State {
     /// When `in_regexp` is true, expanded values are regex-escaped
     /// (equivalent to Go's `regexp.QuoteMeta`).
     pub fn expand_env(&self, s: &str, in_regexp: bool) -> String {
-        crate::parser::expand_env(s, &|key| self.getenv(key).map(|s| s.to_string()), in_regexp)
+        let lookup = |key: &str| self.positional(key).or_else(|| self.getenv(key).map(|s| s.to_string()));
+        crate::parser::expand_env(s, &lookup, in_regexp)
+    }
+
+    /// `$1`, `$2`, ... and `$@` inside a `func` call
+    fn positional(&self, key: &str) -> Option<String> {
+        let args = self.call_args.last()?;
+        match key {
+            "@" => Some(args.join(" ")),
+            _ => {
+                let n: usize = key.parse().ok()?;
+                Some(args.get(n.checked_sub(1)?).cloned().unwrap_or_default())
+            }
+        }
     }
 
     /// Expand environment variables in a string (simple mode, no regexp escaping).
@@ -250,11 +379,26 @@ impl State {
     ///
     /// Go-compatible: file names have environment variables expanded before use,
     /// and paths are resolved relative to pwd (via `resolve_path`).
-    pub fn extract_files(&self, archive: &emx_txtar::Archive) -> Result<(), std::io::Error> {
-        for file in &archive.files {
+    pub fn extract_files(&mut self, archive: &emx_txtar::Archive) -> Result<(), std::io::Error> {
+        self.extract(&archive.files, true)
+    }
+
+    /// Extract files of included archives into the workdir, keeping any
+    /// that already exist there. Unlike `extract_files`, these are not the
+    /// archive's own, so update mode never rewrites them.
+    pub fn extract_included_files(&mut self, files: &[emx_txtar::File]) -> Result<(), std::io::Error> {
+        self.extract(files, false)
+    }
+
+    fn extract(&mut self, files: &[emx_txtar::File], own: bool) -> Result<(), std::io::Error> {
+        for file in files {
             // Go-compatible: expand env vars in file names (like Go's ExtractFiles)
             let expanded_name = self.expand_env(&file.name, false);
-            let path = self.resolve_path(&expanded_name);
+            let path = if own {
+                self.resolve_path(&expanded_name)
+            } else {
+                clean_path(&self.workdir.join(&expanded_name))
+            };
             // Ensure archive entries can't escape workdir (CWE-22)
             self.ensure_within_workdir(&path).map_err(|e| {
                 std::io::Error::new(
@@ -262,31 +406,39 @@ impl State {
                     format!("archive file '{}': {}", file.name, e),
                 )
             })?;
+            if !own && path.exists() {
+                continue;
+            }
             if let Some(parent) = path.parent() {
                 std::fs::create_dir_all(parent)?;
             }
             std::fs::write(&path, &file.data)?;
+            if own {
+                self.archive_files.insert(path, file.name.clone());
+            }
         }
         Ok(())
     }
 
+    /// Text view of stdout for pattern matching and logs; invalid UTF-8
+    /// is replaced, so compare `stdout` itself when bytes matter.
+    pub fn stdout_text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Text view of stderr; see `stdout_text`.
+    pub fn stderr_text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
     /// Read a file, treating "stdout" and "stderr" as virtual files.
     /// Line endings are normalized to LF for consistent comparison.
     pub fn read_file(&self, name: &str) -> Result<String, std::io::Error> {
         let content = match name {
-            "stdout" => self.stdout.clone(),
-            "stderr" => self.stderr.clone(),
+            "stdout" => self.stdout_text().into_owned(),
+            "stderr" => self.stderr_text().into_owned(),
+            _ if name.starts_with("<<") => return self.read_heredoc(name).cloned(),
             _ => {
-                // Check if this is a heredoc virtual file
-                if name.starts_with("<<") {
-                    if let Some(content) = self.heredoc_files.get(name) {
-                        return Ok(content.clone());
-                    }
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        format!("heredoc not found: {}", name)
-                    ));
-                }
                 let path = self.resolve_path(name);
                 std::fs::read_to_string(&path)?
             }
@@ -295,6 +447,74 @@ impl State {
         Ok(content.replace("\r\n", "\n"))
     }
 
+    /// Read a file like `read_file`, but as exact bytes: no UTF-8
+    /// decoding and no line-ending normalization.
+    pub fn read_file_bytes(&self, name: &str) -> Result<Vec<u8>, std::io::Error> {
+        match name {
+            "stdout" => Ok(self.stdout.clone()),
+            "stderr" => Ok(self.stderr.clone()),
+            _ if name.starts_with("<<") => Ok(self.read_heredoc(name)?.clone().into_bytes()),
+            _ => std::fs::read(self.resolve_path(name)),
+        }
+    }
+
+    /// Register a `func` macro, replacing any earlier one of the same name
+    pub fn define_func(&mut self, def: FuncDef, file: impl Into<String>) {
+        let func = ScriptFunc { def, file: file.into() };
+        self.funcs.insert(func.def.name.clone(), Arc::new(func));
+    }
+
+    /// Look up a `func` macro
+    pub fn func(&self, name: &str) -> Option<Arc<ScriptFunc>> {
+        self.funcs.get(name).cloned()
+    }
+
+    /// Arguments of the innermost `func` call in progress
+    pub fn call_args(&self) -> Option<&[String]> {
+        self.call_args.last().map(|args| args.as_slice())
+    }
+
+    /// Number of `func` calls in progress
+    pub fn call_depth(&self) -> usize {
+        self.call_args.len()
+    }
+
+    /// Enter a `func` call with `args` as its positional parameters
+    pub fn push_call(&mut self, args: Vec<String>) {
+        self.call_args.push(args);
+    }
+
+    /// Leave the innermost `func` call
+    pub fn pop_call(&mut self) {
+        self.call_args.pop();
+    }
+
+    /// Canonical paths of the archives being run (the script's own and
+    /// the includes in progress), outermost first
+    pub fn includes(&self) -> &[PathBuf] {
+        &self.includes
+    }
+
+    /// Start running the archive at canonical path `file`
+    pub fn push_include(&mut self, file: PathBuf) {
+        self.includes.push(file);
+    }
+
+    /// Finish the innermost archive being run
+    pub fn pop_include(&mut self) {
+        self.includes.pop();
+    }
+
+    /// Look up a heredoc virtual file
+    fn read_heredoc(&self, name: &str) -> Result<&String, std::io::Error> {
+        self.heredoc_files.get(name).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("heredoc not found: {}", name)
+            )
+        })
+    }
+
     /// Store heredoc content and return a virtual file path
     /// The path can be used with read_file to retrieve the content
     pub fn store_heredoc(&mut self, content: String) -> String {
@@ -303,6 +523,60 @@ impl State {
         self.heredoc_files.insert(virtual_path.clone(), content);
         virtual_path
     }
+
+    /// Like `store_heredoc`, but also remembers the end marker and which
+    /// script lines hold the body, so update mode can rewrite it in place.
+    pub fn store_heredoc_with_source(
+        &mut self,
+        content: String,
+        marker: &str,
+        lines: Range<usize>,
+    ) -> String {
+        let virtual_path = self.store_heredoc(content);
+        self.heredoc_sources.insert(virtual_path.clone(), (marker.to_string(), lines));
+        virtual_path
+    }
+
+    /// Record `content` as the new golden content for `name`.
+    ///
+    /// Only archive files and heredocs can be updated; returns `false` for
+    /// anything else (e.g. `stdout` or files created by the script), and for
+    /// heredoc content the parser would not read back unchanged.
+    /// Archive files are also rewritten in the workdir so later commands
+    /// see the new content.
+    pub fn record_golden_update(&mut self, name: &str, content: &str) -> Result<bool, std::io::Error> {
+        if let Some((marker, lines)) = self.heredoc_sources.get(name).cloned() {
+            // A body line equal to the end marker would truncate the heredoc
+            if content.lines().any(|l| l.trim() == marker) {
+                return Ok(false);
+            }
+            // A body reads back with leading empty lines dropped and exactly
+            // one trailing newline, so other content wouldn't round-trip
+            let representable = content.is_empty()
+                || (content.ends_with('\n') && !content.ends_with("\n\n") && !content.starts_with('\n'));
+            if !representable {
+                return Ok(false);
+            }
+            self.heredoc_files.insert(name.to_string(), content.to_string());
+            self.golden_updates.push(GoldenUpdate::Heredoc {
+                lines,
+                content: content.to_string(),
+            });
+            return Ok(true);
+        }
+
+        let path = self.resolve_path(name);
+        let Some(archive_name) = self.archive_files.get(&path).cloned() else {
+            return Ok(false);
+        };
+        std::fs::write(&path, content)?;
+        self.golden_updates.retain(|u| !matches!(u, GoldenUpdate::File { name, .. } if *name == archive_name));
+        self.golden_updates.push(GoldenUpdate::File {
+            name: archive_name,
+            content: content.to_string(),
+        });
+        Ok(true)
+    }
 }
 
 /// Clean a path by resolving `.` and `..` components lexically.
@@ -31,3 +31,178 @@ fn testscript_all() {
 
     emx_testspec::run_and_assert(dir);
 }
+
+#[test]
+fn testscript_parallel_preserves_order() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/testscript");
+
+    let serial = emx_testspec::run(&dir).run().expect("failed to run tests");
+    let parallel = emx_testspec::run(&dir).jobs(4).run().expect("failed to run tests");
+
+    let names = |r: &emx_testspec::TestResult| -> Vec<String> {
+        r.cases.iter().map(|c| c.name.clone()).collect()
+    };
+    assert_eq!(names(&serial), names(&parallel));
+    assert!(parallel.all_passed(), "{}", parallel.summary());
+}
+
+#[test]
+fn update_mode_rewrites_golden_sections() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("update.txtar");
+    std::fs::write(&script, "\
+exec echo fresh
+cmp stdout golden.txt
+cmp stdout <<EOF
+stale
+EOF
+-- golden.txt --
+stale
+-- other.txt --
+untouched
+").unwrap();
+
+    let result = emx_testspec::run(&script).update(true).run().unwrap();
+    assert!(result.all_passed(), "{}", result.summary());
+
+    let updated = std::fs::read_to_string(&script).unwrap();
+    assert_eq!(updated, "\
+exec echo fresh
+cmp stdout golden.txt
+cmp stdout <<EOF
+fresh
+EOF
+-- golden.txt --
+fresh
+-- other.txt --
+untouched
+");
+}
+
+#[test]
+fn update_mode_keeps_heredocs_it_cannot_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("update.txtar");
+    let source = "\
+exec printf fresh
+cmp stdout <<EOF
+stale
+EOF
+";
+    std::fs::write(&script, source).unwrap();
+
+    let result = emx_testspec::run(&script).update(true).run().unwrap();
+    let case = &result.cases[0];
+    assert!(!case.passed, "{}", case.log);
+    assert_eq!(std::fs::read_to_string(&script).unwrap(), source);
+}
+
+#[cfg(unix)]
+#[test]
+fn timeout_kills_hung_process() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("hang.txtar");
+    std::fs::write(&script, "exec -timeout=200ms sleep 30\n").unwrap();
+
+    let start = std::time::Instant::now();
+    let result = emx_testspec::run(&script).run().unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(result.failed_count(), 1);
+    assert_eq!(result.cases[0].error_kind, Some(emx_testspec::ErrorKind::Timeout));
+}
+
+#[test]
+fn func_errors_report_call_site() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("func.txtar");
+    std::fs::write(&script, "\
+func expect_line text
+    echo $1
+    stdout '^expected$'
+end
+expect_line expected
+expect_line other
+").unwrap();
+
+    let result = emx_testspec::run(&script).run().unwrap();
+    let case = &result.cases[0];
+    assert_eq!(case.error_kind, Some(emx_testspec::ErrorKind::PatternMismatch));
+    assert_eq!(case.error_line, Some(3));
+    let error = case.error.as_deref().unwrap();
+    assert!(error.ends_with(&format!("\tcalled from {}:6", script.display())), "{}", error);
+}
+
+#[test]
+fn waitfor_reports_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("waitfor.txtar");
+    std::fs::write(&script, "waitfor -timeout=50ms file:never.flag\n").unwrap();
+
+    let result = emx_testspec::run(&script).run().unwrap();
+    let case = &result.cases[0];
+    assert_eq!(case.error_kind, Some(emx_testspec::ErrorKind::Timeout));
+    assert!(case.error.as_deref().unwrap().contains("file:never.flag not ready after 50ms"));
+}
+
+#[test]
+fn eventually_usage_errors_are_fatal() {
+    use emx_testspec::ErrorKind;
+
+    let dir = tempfile::tempdir().unwrap();
+    for (name, line, kind) in [
+        ("missing", "! eventually -timeout=1m", ErrorKind::UsageError),
+        ("unknown", "! eventually -timeout=1m nosuchcommand", ErrorKind::SyntaxError),
+        ("duration", "! eventually -timeout=bogus exists x.txt", ErrorKind::SyntaxError),
+    ] {
+        let script = dir.path().join(format!("{}.txtar", name));
+        std::fs::write(&script, format!("{}\n", line)).unwrap();
+
+        let result = emx_testspec::run(&script).run().unwrap();
+        let case = &result.cases[0];
+        assert_eq!(case.error_kind, Some(kind), "{}: {:?}", line, case.error);
+        assert_eq!(case.error_line, Some(1), "{}", line);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn leftover_jobs_are_killed() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("leftover.txtar");
+    std::fs::write(&script, "exec -name=server sleep 30 &\n").unwrap();
+
+    let start = std::time::Instant::now();
+    let result = emx_testspec::run(&script).run().unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    let case = &result.cases[0];
+    assert!(case.passed, "{:?}", case.error);
+    assert!(case.log.contains("[background] server still running at script end, killed"), "{}", case.log);
+}
+
+#[test]
+fn custom_command_replaces_wrapper() {
+    use emx_testspec::{Cmd, CmdResult, CmdUsage, ScriptError, State};
+
+    struct Custom;
+    impl Cmd for Custom {
+        fn run(&self, _state: &mut State, _args: &[String]) -> Result<CmdResult, ScriptError> {
+            Ok(CmdResult::Stop("custom eventually".into()))
+        }
+
+        fn usage(&self) -> CmdUsage {
+            CmdUsage { summary: "custom".into(), args: String::new(), regexp_args: None, async_: false, creates_files: false }
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("custom.txtar");
+    std::fs::write(&script, "eventually nosuchcommand\n").unwrap();
+
+    let mut engine = emx_testspec::Engine::new();
+    engine.register_command("eventually", Box::new(Custom));
+    assert!(engine.wrappers.is_empty());
+    let result = emx_testspec::run(&script).engine(engine).run().unwrap();
+    let case = &result.cases[0];
+    assert!(case.passed, "{:?}", case.error);
+    assert!(case.log.contains("STOP: custom eventually"), "{}", case.log);
+}